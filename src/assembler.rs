@@ -0,0 +1,887 @@
+//! Assembler for the subset of Octo syntax used to write small test ROMs.
+//!
+//! Supported statements:
+//!
+//! - `: name` labels and bare `name` calls, `;` or `return`
+//! - `:const name value`, `:alias name vX` and `:byte value` or bare numbers as raw data
+//! - `vX := n`, `vX := vY`, `vX := random n`, `vX := key`, `vX := delay`, `vX += n` and
+//!   the `:=`, `|=`, `&=`, `^=`, `+=`, `-=`, `=-`, `>>=` and `<<=` register operations
+//! - `i := address`, `i := long address`, `i := hex vX`, `i := bighex vX`, `i += vX`
+//! - `delay := vX`, `buzzer := vX`, `pitch := vX`, `plane n`, `audio`
+//! - `clear`, `sprite vX vY n`, `jump address`, `jump0 address`, `bcd vX`, `save vX`,
+//!   `load vX`, `save vX - vY`, `load vX - vY`, `saveflags vX`, `loadflags vX`
+//! - `hires`, `lores`, `exit`, `scroll-down n`, `scroll-up n`, `scroll-left`, `scroll-right`
+//! - `if vX == n then`, with `==`, `!=`, `key` and `-key` conditions on a value or register
+//! - `loop`, `while condition` and `again`
+//!
+//! When a `: main` label exists and is not the first statement, a jump to it is placed at
+//! 0x200, as Octo does.
+
+use std::collections::{BTreeMap, HashMap};
+use std::{error::Error, fmt};
+
+use crate::instruction::Instruction;
+use crate::program::{MEMORY_SIZE, PROGRAM_STARTING_ADDRESS};
+
+/// Where and why assembling failed, lines and columns count from 1.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AssemblyError {}
+
+/// An assembled ROM and the address of every label.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl Assembly {
+    /// One `<address> <label>` line per label, ordered by address.
+    pub fn symbol_map(&self) -> String {
+        let mut labels: Vec<(&String, &u16)> = self.labels.iter().collect();
+        labels.sort_by_key(|(name, address)| (**address, *name));
+
+        labels
+            .iter()
+            .map(|(name, address)| format!("{:#06x} {}\n", address, name))
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut start = None;
+
+        for (column, character) in line.char_indices().chain([(line.len(), ' ')]) {
+            match (character.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(token_start)) => {
+                    tokens.push(Token {
+                        text: &line[token_start..column],
+                        line: index + 1,
+                        column: line[..token_start].chars().count() + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse::<i32>().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register_name(text: &str) -> Option<u8> {
+    let mut characters = text.chars();
+    match (characters.next(), characters.next(), characters.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+/// Address still waiting for a label to be defined, either for an instruction or as the
+/// word following `i := long`.
+struct Fixup<'a> {
+    offset: usize,
+    label: Token<'a>,
+    instruction: Option<fn(u16) -> Instruction>,
+}
+
+enum Condition {
+    Equal(u8, u8),
+    NotEqual(u8, u8),
+    RegistersEqual(u8, u8),
+    RegistersNotEqual(u8, u8),
+    KeyPressed(u8),
+    KeyNotPressed(u8),
+}
+
+impl Condition {
+    /// Instruction skipping the next one when the condition is false.
+    fn skip_unless(&self) -> Instruction {
+        match *self {
+            Condition::Equal(register, value) => Instruction::SkipIfNotEqual { register, value },
+            Condition::NotEqual(register, value) => Instruction::SkipIfEqual { register, value },
+            Condition::RegistersEqual(register_x, register_y) => {
+                Instruction::SkipIfRegistersNotEqual {
+                    register_x,
+                    register_y,
+                }
+            }
+            Condition::RegistersNotEqual(register_x, register_y) => {
+                Instruction::SkipIfRegistersEqual {
+                    register_x,
+                    register_y,
+                }
+            }
+            Condition::KeyPressed(register) => {
+                Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(register)
+            }
+            Condition::KeyNotPressed(register) => {
+                Instruction::SkipIfPressedKeyContainsRegisterValue(register)
+            }
+        }
+    }
+
+    fn negate(self) -> Condition {
+        match self {
+            Condition::Equal(x, value) => Condition::NotEqual(x, value),
+            Condition::NotEqual(x, value) => Condition::Equal(x, value),
+            Condition::RegistersEqual(x, y) => Condition::RegistersNotEqual(x, y),
+            Condition::RegistersNotEqual(x, y) => Condition::RegistersEqual(x, y),
+            Condition::KeyPressed(x) => Condition::KeyNotPressed(x),
+            Condition::KeyNotPressed(x) => Condition::KeyPressed(x),
+        }
+    }
+}
+
+/// Start of a `loop` and the jumps out of it emitted by `while`.
+struct Loop<'a> {
+    start: u16,
+    token: Token<'a>,
+    exits: Vec<usize>,
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    rom: Vec<u8>,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<&'a str, i32>,
+    aliases: HashMap<&'a str, u8>,
+    fixups: Vec<Fixup<'a>>,
+    loops: Vec<Loop<'a>>,
+}
+
+type Result<T> = std::result::Result<T, AssemblyError>;
+
+fn error_at(token: Token, message: String) -> AssemblyError {
+    AssemblyError {
+        line: token.line,
+        column: token.column,
+        message,
+    }
+}
+
+impl<'a> Assembler<'a> {
+    fn address(&self) -> u16 {
+        PROGRAM_STARTING_ADDRESS.wrapping_add(self.rom.len() as u16)
+    }
+
+    fn next(&mut self) -> Result<Token<'a>> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(*token)
+            }
+            None => {
+                let last = self.tokens.last().copied().unwrap_or(Token {
+                    text: "",
+                    line: 1,
+                    column: 1,
+                });
+                Err(error_at(
+                    last,
+                    format!("Unexpected end of source after {}", last.text),
+                ))
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|token| token.text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(error_at(
+                token,
+                format!("Expected {}, found {}", text, token.text),
+            ));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.rom
+            .extend_from_slice(&instruction.encode().to_be_bytes());
+    }
+
+    fn patch(&mut self, offset: usize, word: u16) {
+        self.rom[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.register_from(token)
+            .ok_or_else(|| error_at(token, format!("Expected a register, found {}", token.text)))
+    }
+
+    fn register_from(&self, token: Token) -> Option<u8> {
+        parse_register_name(token.text).or_else(|| self.aliases.get(token.text).copied())
+    }
+
+    fn value(&mut self, min: i32, max: i32) -> Result<i32> {
+        let token = self.next()?;
+        let value = parse_number(token.text)
+            .or_else(|| self.constants.get(token.text).copied())
+            .ok_or_else(|| error_at(token, format!("Expected a number, found {}", token.text)))?;
+
+        if !(min..=max).contains(&value) {
+            return Err(error_at(
+                token,
+                format!("{} does not fit between {} and {}", value, min, max),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.value(-128, 255)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        Ok(self.value(0, 15)? as u8)
+    }
+
+    /// Reads an address from a number or constant, or records a fixup at `offset` for a
+    /// label, which may be defined later.
+    fn address_operand(
+        &mut self,
+        offset: usize,
+        instruction: Option<fn(u16) -> Instruction>,
+    ) -> Result<u16> {
+        let token = self.next()?;
+        let max = if instruction.is_some() { 0xfff } else { 0xffff };
+
+        match parse_number(token.text).or_else(|| self.constants.get(token.text).copied()) {
+            Some(address) if (0..=max).contains(&address) => Ok(address as u16),
+            Some(address) => Err(error_at(
+                token,
+                format!("Address {:#x} does not fit in {:#x}", address, max),
+            )),
+            None => {
+                self.fixups.push(Fixup {
+                    offset,
+                    label: token,
+                    instruction,
+                });
+                Ok(0)
+            }
+        }
+    }
+
+    /// Emits an instruction taking a 12 bit address.
+    fn emit_address(&mut self, instruction: fn(u16) -> Instruction) -> Result<()> {
+        let address = self.address_operand(self.rom.len(), Some(instruction))?;
+        self.emit(instruction(address));
+        Ok(())
+    }
+
+    /// Emits F000 followed by a 16 bit address.
+    fn emit_long_address(&mut self) -> Result<()> {
+        self.emit(Instruction::StoreNextWordToI);
+        let address = self.address_operand(self.rom.len(), None)?;
+        self.rom.extend_from_slice(&address.to_be_bytes());
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let x = self.register()?;
+        let operator = self.next()?;
+
+        match operator.text {
+            "key" => Ok(Condition::KeyPressed(x)),
+            "-key" => Ok(Condition::KeyNotPressed(x)),
+            "==" | "!=" => {
+                let equal = operator.text == "==";
+                let operand = self.tokens.get(self.position).copied();
+                match operand.and_then(|operand| self.register_from(operand)) {
+                    Some(y) => {
+                        self.position += 1;
+                        Ok(if equal {
+                            Condition::RegistersEqual(x, y)
+                        } else {
+                            Condition::RegistersNotEqual(x, y)
+                        })
+                    }
+                    None => {
+                        let value = self.byte()?;
+                        Ok(if equal {
+                            Condition::Equal(x, value)
+                        } else {
+                            Condition::NotEqual(x, value)
+                        })
+                    }
+                }
+            }
+            _ => Err(error_at(
+                operator,
+                format!("Unsupported condition {}", operator.text),
+            )),
+        }
+    }
+
+    fn define_label(&mut self, token: Token<'a>) -> Result<()> {
+        if parse_register_name(token.text).is_some() || parse_number(token.text).is_some() {
+            return Err(error_at(
+                token,
+                format!("{} is not a valid label name", token.text),
+            ));
+        }
+
+        let address = self.address();
+        if self
+            .labels
+            .insert(token.text.to_string(), address)
+            .is_some()
+        {
+            return Err(error_at(
+                token,
+                format!("Label {} is already defined", token.text),
+            ));
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, register: u8) -> Result<()> {
+        let operator = self.next()?;
+
+        if operator.text == ":=" {
+            let instruction = match self.peek() {
+                Some("random") => {
+                    self.position += 1;
+                    let mask = self.byte()?;
+                    Some(Instruction::SetRandomNumber { register, mask })
+                }
+                Some("key") => {
+                    self.position += 1;
+                    Some(Instruction::HaltAndGetKey(register))
+                }
+                Some("delay") => {
+                    self.position += 1;
+                    Some(Instruction::SetRegisterFromDelayTimer(register))
+                }
+                _ => None,
+            };
+            if let Some(instruction) = instruction {
+                self.emit(instruction);
+                return Ok(());
+            }
+        }
+
+        let operand = self.tokens.get(self.position).copied();
+        if let Some(register_y) = operand.and_then(|operand| self.register_from(operand)) {
+            self.position += 1;
+            let register_x = register;
+            let instruction = match operator.text {
+                ":=" => Instruction::StoreYToX {
+                    register_x,
+                    register_y,
+                },
+                "|=" => Instruction::OrRegisters {
+                    register_x,
+                    register_y,
+                },
+                "&=" => Instruction::AndRegisters {
+                    register_x,
+                    register_y,
+                },
+                "^=" => Instruction::XorRegisters {
+                    register_x,
+                    register_y,
+                },
+                "+=" => Instruction::AddRegisters {
+                    register_x,
+                    register_y,
+                },
+                "-=" => Instruction::SubtractXMinusY {
+                    register_x,
+                    register_y,
+                },
+                ">>=" => Instruction::ShiftRegisterRight {
+                    register_x,
+                    register_y,
+                },
+                "=-" => Instruction::SubtractYMinusX {
+                    register_x,
+                    register_y,
+                },
+                "<<=" => Instruction::ShiftRegisterLeft {
+                    register_x,
+                    register_y,
+                },
+                _ => {
+                    return Err(error_at(
+                        operator,
+                        format!("Unknown register operation {}", operator.text),
+                    ))
+                }
+            };
+            self.emit(instruction);
+            return Ok(());
+        }
+
+        if operator.text != ":=" && operator.text != "+=" {
+            return Err(error_at(
+                operator,
+                format!("{} needs a register operand", operator.text),
+            ));
+        }
+        let value = self.byte()?;
+        self.emit(if operator.text == ":=" {
+            Instruction::SetV { register, value }
+        } else {
+            Instruction::AddToRegister { register, value }
+        });
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next()?;
+
+        match token.text {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(name)?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.value(i32::MIN, i32::MAX)?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":byte" => {
+                let value = self.byte()?;
+                self.rom.push(value);
+            }
+            ";" | "return" => self.emit(Instruction::ReturnFromSubroutine),
+            "clear" => self.emit(Instruction::ClearScreen),
+            "exit" => self.emit(Instruction::Exit),
+            "lores" => self.emit(Instruction::LowResolution),
+            "hires" => self.emit(Instruction::HighResolution),
+            "scroll-right" => self.emit(Instruction::ScrollRight),
+            "scroll-left" => self.emit(Instruction::ScrollLeft),
+            "scroll-down" => {
+                let rows = self.nibble()?;
+                self.emit(Instruction::ScrollDown(rows));
+            }
+            "scroll-up" => {
+                let rows = self.nibble()?;
+                self.emit(Instruction::ScrollUp(rows));
+            }
+            "audio" => self.emit(Instruction::LoadAudioPattern),
+            "plane" => {
+                let planes = self.nibble()?;
+                self.emit(Instruction::SelectPlanes(planes));
+            }
+            "jump" => self.emit_address(Instruction::JumpToAddress)?,
+            "jump0" => self.emit_address(Instruction::JumpWithOffset)?,
+            "sprite" => {
+                let register_x = self.register()?;
+                let register_y = self.register()?;
+                let bytes = self.nibble()?;
+                self.emit(Instruction::Draw {
+                    register_x,
+                    register_y,
+                    bytes,
+                });
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let register = self.register()?;
+                self.emit(match token.text {
+                    "bcd" => Instruction::BinaryRepresentationFromRegister(register),
+                    "saveflags" => Instruction::SaveFlags(register),
+                    _ => Instruction::LoadFlags(register),
+                });
+            }
+            "save" | "load" => {
+                let save = token.text == "save";
+                let register_x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.position += 1;
+                    let register_y = self.register()?;
+                    self.emit(if save {
+                        Instruction::SaveRegisterRange {
+                            register_x,
+                            register_y,
+                        }
+                    } else {
+                        Instruction::LoadRegisterRange {
+                            register_x,
+                            register_y,
+                        }
+                    });
+                } else if save {
+                    self.emit(Instruction::SaveRegisters(register_x));
+                } else {
+                    self.emit(Instruction::LoadRegisters(register_x));
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let register = self.register()?;
+                self.emit(match token.text {
+                    "delay" => Instruction::SetDelayTimerFromRegister(register),
+                    "buzzer" => Instruction::SetSoundTimerFromRegister(register),
+                    _ => Instruction::SetPitchFromRegister(register),
+                });
+            }
+            "i" => {
+                let operator = self.next()?;
+                match (operator.text, self.peek()) {
+                    ("+=", _) => {
+                        let register = self.register()?;
+                        self.emit(Instruction::AddRegisterToI(register));
+                    }
+                    (":=", Some("long")) => {
+                        self.position += 1;
+                        self.emit_long_address()?;
+                    }
+                    (":=", Some(font @ ("hex" | "bighex"))) => {
+                        self.position += 1;
+                        let register = self.register()?;
+                        self.emit(if font == "hex" {
+                            Instruction::SetIToFontLocation(register)
+                        } else {
+                            Instruction::SetIToBigFontLocation(register)
+                        });
+                    }
+                    (":=", _) => self.emit_address(Instruction::StoreAddrToI)?,
+                    _ => {
+                        return Err(error_at(
+                            operator,
+                            format!("Expected := or += after i, found {}", operator.text),
+                        ))
+                    }
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                self.expect("then")?;
+                self.emit(condition.skip_unless());
+            }
+            "loop" => self.loops.push(Loop {
+                start: self.address(),
+                token,
+                exits: Vec::new(),
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                let offset = self.rom.len() + 2;
+                self.emit(condition.negate().skip_unless());
+                self.emit(Instruction::JumpToAddress(0));
+                self.loops
+                    .last_mut()
+                    .ok_or_else(|| error_at(token, "while outside of a loop".to_string()))?
+                    .exits
+                    .push(offset);
+            }
+            "again" => {
+                let ended = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| error_at(token, "again without a loop".to_string()))?;
+                self.emit(Instruction::JumpToAddress(ended.start));
+                let exit = Instruction::JumpToAddress(self.address()).encode();
+                for offset in ended.exits {
+                    self.patch(offset, exit);
+                }
+            }
+            _ => {
+                if let Some(x) = self.register_from(token) {
+                    return self.register_statement(x);
+                }
+                if let Some(value) =
+                    parse_number(token.text).or_else(|| self.constants.get(token.text).copied())
+                {
+                    if !(-128..=255).contains(&value) {
+                        return Err(error_at(token, format!("{} does not fit in a byte", value)));
+                    }
+                    self.rom.push(value as u8);
+                    return Ok(());
+                }
+
+                // Anything else names a subroutine
+                self.position -= 1;
+                self.emit_address(Instruction::CallSubroutineAtAddress)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_fixups(&mut self) -> Result<()> {
+        for index in 0..self.fixups.len() {
+            let Fixup {
+                offset,
+                label,
+                instruction,
+            } = self.fixups[index];
+            let address = *self
+                .labels
+                .get(label.text)
+                .ok_or_else(|| error_at(label, format!("Undefined label {}", label.text)))?;
+
+            let word = match instruction {
+                None => address,
+                Some(_) if address > 0xfff => {
+                    return Err(error_at(
+                        label,
+                        format!(
+                            "Label {} at {:#x} is out of reach, use i := long",
+                            label.text, address
+                        ),
+                    ))
+                }
+                Some(instruction) => instruction(address).encode(),
+            };
+            self.patch(offset, word);
+        }
+
+        Ok(())
+    }
+}
+
+/// Assembles Octo source into a ROM image to be loaded at 0x200.
+pub fn assemble(source: &str) -> std::result::Result<Assembly, AssemblyError> {
+    let tokens = tokenize(source);
+    let main = tokens
+        .windows(2)
+        .position(|pair| pair[0].text == ":" && pair[1].text == "main");
+
+    let mut assembler = Assembler {
+        tokens,
+        position: 0,
+        rom: Vec::new(),
+        labels: BTreeMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+    };
+
+    if let Some(index) = main.filter(|index| *index > 0) {
+        let main = assembler.tokens[index + 1];
+        assembler.fixups.push(Fixup {
+            offset: 0,
+            label: main,
+            instruction: Some(Instruction::JumpToAddress),
+        });
+        assembler.emit(Instruction::JumpToAddress(0));
+    }
+
+    let max_len = MEMORY_SIZE - PROGRAM_STARTING_ADDRESS as usize;
+    while assembler.position < assembler.tokens.len() {
+        let token = assembler.tokens[assembler.position];
+        assembler.statement()?;
+        if assembler.rom.len() > max_len {
+            return Err(error_at(
+                token,
+                format!("Program grows past {} bytes", max_len),
+            ));
+        }
+    }
+
+    if let Some(unclosed) = assembler.loops.pop() {
+        return Err(error_at(unclosed.token, "loop without again".to_string()));
+    }
+    assembler.resolve_fixups()?;
+
+    Ok(Assembly {
+        rom: assembler.rom,
+        labels: assembler.labels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::{parse_opcode, Instruction};
+
+    fn instructions(rom: &[u8]) -> Vec<Instruction> {
+        rom.chunks(2)
+            .map(|word| parse_opcode(u16::from_be_bytes([word[0], word[1]])).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn assemble_test() {
+        let source = "
+            :const SPEED 2
+            :alias x v3
+
+            : draw
+              i := ball
+              sprite x v4 1
+            ;
+
+            : main
+              clear
+              x := 0x10
+              loop
+                draw
+                x += SPEED
+                if v0 key then v1 := random 0xff
+                while x != 40
+              again
+              jump main
+
+            : ball
+              0x80 0b01000000
+        ";
+
+        let assembly = assemble(source).unwrap();
+        let rom = assembly.rom;
+
+        assert_eq!(
+            instructions(&rom[..rom.len() - 2]),
+            vec![
+                Instruction::JumpToAddress(0x208),
+                Instruction::StoreAddrToI(0x21c),
+                Instruction::Draw {
+                    register_x: 3,
+                    register_y: 4,
+                    bytes: 1
+                },
+                Instruction::ReturnFromSubroutine,
+                Instruction::ClearScreen,
+                Instruction::SetV {
+                    register: 3,
+                    value: 0x10
+                },
+                Instruction::CallSubroutineAtAddress(0x202),
+                Instruction::AddToRegister {
+                    register: 3,
+                    value: 2
+                },
+                Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(0),
+                Instruction::SetRandomNumber {
+                    register: 1,
+                    mask: 0xff
+                },
+                Instruction::SkipIfNotEqual {
+                    register: 3,
+                    value: 40
+                },
+                Instruction::JumpToAddress(0x21a),
+                Instruction::JumpToAddress(0x20c),
+                Instruction::JumpToAddress(0x208),
+            ]
+        );
+        assert_eq!(&rom[rom.len() - 2..], &[0x80, 0x40]);
+
+        assert_eq!(assembly.labels.get("ball"), Some(&0x21c));
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let source = "
+            : main
+            v1 := v2 v1 |= v2 v1 &= v2 v1 ^= v2 v1 += v2 v1 -= v2 v1 >>= v2 v1 =- v2
+            v1 <<= v2 v1 := key v1 := delay delay := v1 buzzer := v1 i += v1
+            i := hex v1 i := bighex v1 bcd v1 save v1 load v1 save v1 - v2 load v2 - v1
+            saveflags v1 loadflags v1 hires lores exit scroll-down 3 scroll-up 2
+            scroll-left scroll-right plane 3 audio pitch := v1 jump0 0x300
+            if v1 == v2 then if v1 != v2 then if v1 -key then if v1 != 1 then
+            return
+        ";
+
+        let rom = assemble(source).unwrap().rom;
+        let decoded = instructions(&rom);
+        assert_eq!(decoded.len(), rom.len() / 2);
+        assert_eq!(
+            decoded[decoded.len() - 5..],
+            [
+                Instruction::SkipIfRegistersNotEqual {
+                    register_x: 1,
+                    register_y: 2
+                },
+                Instruction::SkipIfRegistersEqual {
+                    register_x: 1,
+                    register_y: 2
+                },
+                Instruction::SkipIfPressedKeyContainsRegisterValue(1),
+                Instruction::SkipIfEqual {
+                    register: 1,
+                    value: 1
+                },
+                Instruction::ReturnFromSubroutine,
+            ]
+        );
+
+        let long = assemble("i := long data : data").unwrap();
+        assert_eq!(long.rom, vec![0xf0, 0x00, 0x02, 0x04]);
+    }
+
+    #[test]
+    fn error_test() {
+        assert_eq!(
+            assemble("clear\n  v0 := 300"),
+            Err(AssemblyError {
+                line: 2,
+                column: 9,
+                message: "300 does not fit between -128 and 255".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("jump nowhere").unwrap_err().to_string(),
+            "1:6: Undefined label nowhere"
+        );
+        assert_eq!(
+            assemble("loop clear").unwrap_err().to_string(),
+            "1:1: loop without again"
+        );
+        assert!(assemble(": a : a").is_err());
+        assert!(assemble("i := 0x1000").is_err());
+    }
+
+    #[test]
+    fn symbol_map_test() {
+        let assembly = assemble(": start clear : end ;").unwrap();
+        assert_eq!(assembly.symbol_map(), "0x0200 start\n0x0202 end\n");
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    env,
+    io::{self, BufRead, Write},
+    net::TcpListener,
+    process,
+};
+
+use chip_8_emulator::cli::{
+    args_with_config, option_value, MachineOptions, CONFIG_USAGE, MACHINE_OPTIONS_USAGE,
+};
+use chip_8_emulator::debugger::{Breakpoint, Debugger, StopReason, Watchpoint};
+use chip_8_emulator::gdb::GdbStub;
+use chip_8_emulator::instruction::parse_opcode;
+use chip_8_emulator::program::Machine;
+
+/// Instructions `continue`, `next` and `finish` run before giving control back, about
+/// 25 minutes of emulated time at the default speed.
+const DEFAULT_INSTRUCTION_LIMIT: u64 = 1_000_000;
+
+const DEFAULT_DUMP_LEN: usize = 64;
+
+const HELP: &str = "\
+break <address>             stop before the instruction at an address
+break opcode <pattern>      stop before an opcode, non-hex characters match anything, e.g. Dxyn
+break instr <name>          stop before an Instruction variant, e.g. Draw
+watch mem <address>         stop after a write to a memory address
+watch reg V0-VF|I           stop after a write to a register
+delete break|watch <index>  remove a breakpoint or watchpoint
+list                        show breakpoints and watchpoints
+step [count]                execute instructions, entering subroutines
+next                        execute one instruction, running over subroutine calls
+finish                      run until the current subroutine returns
+continue                    run until a breakpoint, watchpoint, exit or error
+regs                        show V0-VF, I, PC and the timers
+stack                       show the call stack
+mem <address> [length]      dump length bytes of memory, 64 by default
+key press|release <key>     press or release a keypad key
+quit                        exit the debugger
+Addresses and keys are hex, with or without 0x. Counts and lengths are decimal.";
+
+fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex number {}", value))
+}
+
+fn parse_register(name: &str) -> Result<Watchpoint, String> {
+    if name.eq_ignore_ascii_case("i") {
+        return Ok(Watchpoint::I);
+    }
+
+    name.strip_prefix(|prefix| prefix == 'V' || prefix == 'v')
+        .and_then(|register| u8::from_str_radix(register, 16).ok())
+        .filter(|register| *register <= 0xf)
+        .map(Watchpoint::Register)
+        .ok_or(format!("Invalid register {}", name))
+}
+
+fn format_instruction(machine: &Machine, address: u16) -> String {
+    match machine.opcode_at(address) {
+        Some(opcode) => match parse_opcode(opcode) {
+            Some(instruction) => format!(
+                "{:#06x}: {:04X} {}",
+                address,
+                opcode,
+                instruction.to_string_with(&machine.quirks())
+            ),
+            None => format!("{:#06x}: {:04X} unknown", address, opcode),
+        },
+        None => format!("{:#06x}: out of memory", address),
+    }
+}
+
+fn print_registers(machine: &Machine) {
+    for (row, registers) in machine.registers().chunks(8).enumerate() {
+        let line: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(index, value)| format!("V{:X}={:02X}", row * 8 + index, value))
+            .collect();
+        println!("{}", line.join(" "));
+    }
+    println!(
+        "I={:04X} PC={:04X} DT={:02X} ST={:02X}",
+        machine.i(),
+        machine.program_counter(),
+        machine.delay_timer(),
+        machine.sound_timer()
+    );
+}
+
+fn print_stack(machine: &Machine) {
+    if machine.stack().is_empty() {
+        println!("Stack is empty");
+    }
+    for (depth, address) in machine.stack().iter().enumerate().rev() {
+        println!("#{} return to {:#06x}", depth, address);
+    }
+}
+
+fn print_memory(machine: &Machine, start: usize, len: usize) {
+    let memory = machine.memory();
+    let end = (start + len).min(memory.len());
+
+    for row_start in (start..end).step_by(16) {
+        let bytes: Vec<String> = memory[row_start..end.min(row_start + 16)]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        println!("{:#06x}: {}", row_start, bytes.join(" "));
+    }
+}
+
+fn print_stop(debugger: &Debugger, machine: &Machine, reason: StopReason) {
+    match reason {
+        StopReason::Stepped => {}
+        StopReason::Breakpoint(index) => {
+            println!("Breakpoint {}: {}", index, debugger.breakpoints[index])
+        }
+        StopReason::Watchpoint(index) => {
+            println!("Watchpoint {}: {}", index, debugger.watchpoints[index])
+        }
+        StopReason::Halted => println!("Program exited"),
+        StopReason::Limit => println!(
+            "Stopped after {} instructions without a break",
+            DEFAULT_INSTRUCTION_LIMIT
+        ),
+        StopReason::Error(error) => println!("{}", error),
+    }
+    println!("{}", format_instruction(machine, machine.program_counter()));
+}
+
+/// Runs one command line, returning false when the debugger should exit.
+fn execute_command(
+    debugger: &mut Debugger,
+    machine: &mut Machine,
+    line: &str,
+) -> Result<bool, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let missing = || "Missing argument, see help".to_string();
+
+    match words.as_slice() {
+        [] => {}
+        ["help" | "h"] => println!("{}", HELP),
+        ["quit" | "q"] => return Ok(false),
+        ["break" | "b", "opcode", pattern] => debugger
+            .breakpoints
+            .push(Breakpoint::Opcode(pattern.parse()?)),
+        ["break" | "b", "instr", name] => debugger
+            .breakpoints
+            .push(Breakpoint::Instruction(name.to_string())),
+        ["break" | "b", address] => debugger
+            .breakpoints
+            .push(Breakpoint::Address(parse_hex(address)?)),
+        ["watch" | "w", "mem", address] => debugger
+            .watchpoints
+            .push(Watchpoint::Memory(parse_hex(address)?)),
+        ["watch" | "w", "reg", register] => debugger.watchpoints.push(parse_register(register)?),
+        ["delete" | "d", kind, index] => {
+            let index: usize = index
+                .parse()
+                .map_err(|_| format!("Invalid index {}", index))?;
+            let removed = match *kind {
+                "break" if index < debugger.breakpoints.len() => {
+                    debugger.breakpoints.remove(index).to_string()
+                }
+                "watch" if index < debugger.watchpoints.len() => {
+                    debugger.watchpoints.remove(index).to_string()
+                }
+                _ => return Err(format!("No {} {}", kind, index)),
+            };
+            println!("Deleted {}", removed);
+        }
+        ["list" | "l"] => {
+            for (index, breakpoint) in debugger.breakpoints.iter().enumerate() {
+                println!("break {}: {}", index, breakpoint);
+            }
+            for (index, watchpoint) in debugger.watchpoints.iter().enumerate() {
+                println!("watch {}: {}", index, watchpoint);
+            }
+        }
+        ["step" | "s"] => {
+            let reason = debugger.step(machine);
+            print_stop(debugger, machine, reason);
+        }
+        ["step" | "s", count] => {
+            let count: u64 = count
+                .parse()
+                .map_err(|_| format!("Invalid count {}", count))?;
+            let mut reason = StopReason::Stepped;
+            for _ in 0..count {
+                reason = debugger.step(machine);
+                if reason != StopReason::Stepped {
+                    break;
+                }
+            }
+            print_stop(debugger, machine, reason);
+        }
+        ["next" | "n"] => {
+            let reason = debugger.step_over(machine, DEFAULT_INSTRUCTION_LIMIT);
+            print_stop(debugger, machine, reason);
+        }
+        ["finish" | "f"] => {
+            if machine.stack().is_empty() {
+                return Err("Not inside a subroutine".to_string());
+            }
+            let reason = debugger.step_out(machine, DEFAULT_INSTRUCTION_LIMIT);
+            print_stop(debugger, machine, reason);
+        }
+        ["continue" | "c"] => {
+            let reason = debugger.resume(machine, DEFAULT_INSTRUCTION_LIMIT);
+            print_stop(debugger, machine, reason);
+        }
+        ["regs" | "r"] => print_registers(machine),
+        ["stack" | "bt"] => print_stack(machine),
+        ["mem" | "x", address] => {
+            print_memory(machine, parse_hex(address)? as usize, DEFAULT_DUMP_LEN)
+        }
+        ["mem" | "x", address, len] => {
+            let len: usize = len.parse().map_err(|_| format!("Invalid length {}", len))?;
+            print_memory(machine, parse_hex(address)? as usize, len);
+        }
+        ["key", action, key] => {
+            let key = parse_hex(key)?;
+            if key > 0xf {
+                return Err("Key must be a hex digit from 0 to F".to_string());
+            }
+            match *action {
+                "press" => machine.key_press(key as u8),
+                "release" => machine.key_release(key as u8),
+                _ => return Err("Expected press or release".to_string()),
+            }
+        }
+        ["break" | "b" | "watch" | "w" | "delete" | "d" | "mem" | "x" | "key", ..] => {
+            return Err(missing())
+        }
+        [command, ..] => return Err(format!("Unknown command {}, see help", command)),
+    }
+
+    Ok(true)
+}
+
+/// Waits for one GDB client on a TCP address, or on a Unix socket when `unix` is set, and
+/// serves it until it detaches.
+fn serve_gdb(
+    machine: &mut Machine,
+    debugger: Debugger,
+    address: &str,
+    unix: bool,
+) -> Result<(), String> {
+    let io_error = |error: io::Error| format!("GDB connection on {} failed: {}", address, error);
+
+    println!("Waiting for GDB on {}", address);
+    if unix {
+        #[cfg(unix)]
+        {
+            let listener = std::os::unix::net::UnixListener::bind(address).map_err(io_error)?;
+            let (stream, _) = listener.accept().map_err(io_error)?;
+            let result = GdbStub::new(stream, debugger).serve(machine);
+            let _ = std::fs::remove_file(address);
+            return result.map_err(io_error);
+        }
+        #[cfg(not(unix))]
+        return Err("Unix sockets are not supported on this platform".to_string());
+    }
+
+    let listener = TcpListener::bind(address).map_err(io_error)?;
+    let (stream, _) = listener.accept().map_err(io_error)?;
+    GdbStub::new(stream, debugger)
+        .serve(machine)
+        .map_err(io_error)
+}
+
+/// Reads commands from stdin until `quit` or the end of input.
+fn repl(debugger: &mut Debugger, machine: &mut Machine) -> Result<(), String> {
+    println!("{}", format_instruction(machine, machine.program_counter()));
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(chip-8) ");
+        io::stdout().flush().map_err(|error| error.to_string())?;
+
+        let line = match lines.next() {
+            Some(line) => line.map_err(|error| error.to_string())?,
+            None => return Ok(()),
+        };
+
+        match execute_command(debugger, machine, &line) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(message) => println!("{}", message),
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args = &args_with_config(args.to_vec())?;
+    let file_name = args.get(1).ok_or(format!(
+        "Usage: chip-8-debugger <rom> {} {} [--gdb host:port | --gdb-socket path]",
+        CONFIG_USAGE, MACHINE_OPTIONS_USAGE
+    ))?;
+
+    let options = MachineOptions::from_args(args)?;
+    let mut machine = options.load_machine(file_name)?;
+    let mut debugger = Debugger::new(options.instructions_per_frame);
+
+    let result = if let Some(address) = option_value(args, "--gdb")? {
+        serve_gdb(&mut machine, debugger, address, false)
+    } else if let Some(path) = option_value(args, "--gdb-socket")? {
+        serve_gdb(&mut machine, debugger, path, true)
+    } else {
+        repl(&mut debugger, &mut machine)
+    };
+
+    machine
+        .flush_trace()
+        .map_err(|error| format!("Trace write failed: {}", error))?;
+    result
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if let Err(message) = run(&args) {
+        eprintln!("{}", message);
+        process::exit(2);
+    }
+}
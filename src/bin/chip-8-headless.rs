@@ -0,0 +1,231 @@
+use std::{env, fmt::Write, fs, process};
+
+use chip_8_emulator::cli::{
+    args_with_config, has_flag, option_value, palette_from_args, parse_option, MachineOptions,
+    CONFIG_USAGE, MACHINE_OPTIONS_USAGE, PALETTE_OPTIONS_USAGE,
+};
+use chip_8_emulator::flicker::FlickerFilter;
+use chip_8_emulator::input::parse_key_script;
+use chip_8_emulator::instruction::{parse_opcode, Instruction};
+use chip_8_emulator::movie::{Movie, Replay};
+use chip_8_emulator::program::Machine;
+use chip_8_emulator::recording::{VideoFormat, VideoRecorder};
+use chip_8_emulator::screenshot::ImageFormat;
+
+const DEFAULT_FRAMES: u64 = 3600;
+
+const EXIT_MACHINE_ERROR: i32 = 1;
+const EXIT_INVALID_USAGE: i32 = 2;
+const EXIT_REPLAY_DIVERGED: i32 = 3;
+
+/// Whether the next instruction jumps to itself, the usual way test ROMs signal they are done.
+fn is_jumping_to_itself(machine: &Machine) -> bool {
+    let address = machine.program_counter();
+
+    machine.opcode_at(address).and_then(parse_opcode) == Some(Instruction::JumpToAddress(address))
+}
+
+/// One line per row, `.` for unlit pixels and the colour index for lit ones.
+fn format_screen(machine: &Machine) -> String {
+    let mut screen = String::new();
+    for row in machine.get_pixel_buffer().rows() {
+        for pixel in row {
+            screen.push(match pixel {
+                0 => '.',
+                _ => (b'0' + pixel) as char,
+            });
+        }
+        screen.push('\n');
+    }
+    screen
+}
+
+fn format_registers(machine: &Machine) -> String {
+    let mut registers = String::new();
+    writeln!(registers, "PC {:#06x}", machine.program_counter()).unwrap();
+    writeln!(registers, "I  {:#06x}", machine.i()).unwrap();
+    writeln!(registers, "DT {}", machine.delay_timer()).unwrap();
+    writeln!(registers, "ST {}", machine.sound_timer()).unwrap();
+    for (index, value) in machine.registers().iter().enumerate() {
+        writeln!(registers, "V{:X} {:#04x}", index, value).unwrap();
+    }
+
+    let stack: Vec<String> = machine
+        .stack()
+        .iter()
+        .map(|address| format!("{:#06x}", address))
+        .collect();
+    writeln!(registers, "SP {}", stack.join(" ")).unwrap();
+
+    registers
+}
+
+fn write_file(path: &str, contents: &[u8]) -> Result<(), String> {
+    fs::write(path, contents).map_err(|_| format!("Write failed to {}", path))
+}
+
+fn run(args: &[String]) -> Result<i32, String> {
+    let args = &args_with_config(args.to_vec())?;
+    let file_name = args.get(1).ok_or(format!(
+        "Usage: chip-8-headless <rom> {} {} {} [--frames frames] [--stop-on-loop] \
+         [--keys key-script | --replay movie] [--record movie] \
+         [--video file|- [--video-format gif|raw] [--video-scale scale]] \
+         [--anti-flicker off|blend|phosphor[:frames]] [--dump-screen file] \
+         [--dump-image file.png|file.ppm [--image-scale scale]] [--dump-registers file] \
+         [--dump-memory file]",
+        CONFIG_USAGE, MACHINE_OPTIONS_USAGE, PALETTE_OPTIONS_USAGE
+    ))?;
+
+    let mut options = MachineOptions::from_args(args)?;
+    // videos and images show the frames as presented, after flicker reduction
+    let mut flicker = FlickerFilter::new(parse_option(args, "--anti-flicker")?.unwrap_or_default());
+    let colors = flicker.colors(&palette_from_args(args)?);
+    let rom = fs::read(file_name).map_err(|_| format!("Read failed from {}", file_name))?;
+
+    let mut replay = match option_value(args, "--replay")? {
+        Some(_) if has_flag(args, "--keys") => {
+            return Err("--replay cannot be combined with --keys".to_string())
+        }
+        Some(path) => {
+            let movie = Movie::load(path)?;
+            movie.check_rom(&rom)?;
+            movie.apply_options(&mut options)?;
+            Some(Replay::new(movie))
+        }
+        None => None,
+    };
+    let record_path = option_value(args, "--record")?;
+    let mut movie = record_path.map(|_| Movie::new(&rom, &options));
+
+    let frames = match parse_option(args, "--frames")? {
+        Some(frames) => frames,
+        None => replay
+            .as_ref()
+            .map(|replay| replay.movie().len())
+            .unwrap_or(DEFAULT_FRAMES),
+    };
+    let stop_on_loop = has_flag(args, "--stop-on-loop");
+
+    let key_events = match option_value(args, "--keys")? {
+        Some(path) => {
+            let script =
+                fs::read_to_string(path).map_err(|_| format!("Read failed from {}", path))?;
+            parse_key_script(&script).map_err(|message| format!("{}: {}", path, message))?
+        }
+        None => Vec::new(),
+    };
+
+    let video_path = option_value(args, "--video")?;
+    let mut video = match video_path {
+        Some(path) => {
+            let format = VideoFormat::resolve(path, parse_option(args, "--video-format")?)?;
+            let scale = parse_option(args, "--video-scale")?.unwrap_or(1);
+            Some(VideoRecorder::create(path, format, &colors, scale)?)
+        }
+        None => None,
+    };
+    // stdout carries the video, so the summary goes to stderr instead
+    let video_to_stdout = video_path == Some("-");
+
+    let mut machine = options.load_machine(file_name)?;
+    let mut key_events = key_events.into_iter().peekable();
+    let mut presented = machine.get_pixel_buffer().clone();
+
+    let mut frame = 0;
+    let mut result = Ok(());
+    let mut divergence = None;
+    while frame < frames {
+        while let Some(event) = key_events.next_if(|event| event.frame <= frame) {
+            event.apply(&mut machine);
+            if let Some(movie) = &mut movie {
+                movie.record_key(event.key, event.pressed);
+            }
+        }
+        if let Some(replay) = &mut replay {
+            replay.apply_inputs(&mut machine);
+        }
+
+        result = machine.run_frame(options.instructions_per_frame);
+        if result.is_err() {
+            break;
+        }
+        frame += 1;
+
+        if let Some(movie) = &mut movie {
+            movie.record_frame(&machine);
+        }
+        presented = flicker.apply(machine.get_pixel_buffer());
+        if let Some(video) = &mut video {
+            video.record_frame(&presented)?;
+        }
+        if let Some(replay) = &mut replay {
+            if let Err(message) = replay.end_frame(&machine) {
+                divergence = Some(message);
+                break;
+            }
+        }
+
+        if machine.is_halted() || (stop_on_loop && is_jumping_to_itself(&machine)) {
+            break;
+        }
+    }
+
+    machine
+        .flush_trace()
+        .map_err(|error| format!("Trace write failed: {}", error))?;
+
+    if let (Some(path), Some(movie)) = (record_path, &movie) {
+        movie.save(path)?;
+    }
+    if let Some(video) = video {
+        video.finish()?;
+    }
+    if let Some(path) = option_value(args, "--dump-screen")? {
+        write_file(path, format_screen(&machine).as_bytes())?;
+    }
+    if let Some(path) = option_value(args, "--dump-image")? {
+        let format = ImageFormat::from_path(path).ok_or(format!(
+            "Unknown image format for {}, expected .png or .ppm",
+            path
+        ))?;
+        let scale = parse_option(args, "--image-scale")?.unwrap_or(1);
+        presented.save_image(path, format, &colors, scale)?;
+    }
+    if let Some(path) = option_value(args, "--dump-registers")? {
+        write_file(path, format_registers(&machine).as_bytes())?;
+    }
+    if let Some(path) = option_value(args, "--dump-memory")? {
+        write_file(path, machine.memory())?;
+    }
+
+    if let Some(message) = divergence {
+        eprintln!("{}", message);
+        return Ok(EXIT_REPLAY_DIVERGED);
+    }
+
+    match result {
+        Ok(()) => {
+            if video_to_stdout {
+                eprintln!("Ran {} frames", frame);
+            } else {
+                println!("Ran {} frames", frame);
+            }
+            Ok(0)
+        }
+        Err(error) => {
+            eprintln!("Stopped after {} frames: {}", frame, error);
+            Ok(EXIT_MACHINE_ERROR)
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let code = run(&args).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        EXIT_INVALID_USAGE
+    });
+
+    process::exit(code);
+}
@@ -0,0 +1,65 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    process,
+};
+
+use chip_8_emulator::assembler::assemble;
+use chip_8_emulator::cli::{option_value, parse_option};
+use chip_8_emulator::disassembler::disassemble;
+
+const USAGE: &str = "Usage:
+  chip-8-tools disasm <rom> [--output file] [--quirks original|vip|chip48|schip]
+  chip-8-tools asm <source> [--output file] [--symbols file]";
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|_| format!("Read failed from {}", path))
+}
+
+/// Writes to the file given with `--output`, or to stdout without one.
+fn write_output(args: &[String], contents: &[u8]) -> Result<(), String> {
+    match option_value(args, "--output")? {
+        Some(path) => fs::write(path, contents).map_err(|_| format!("Write failed to {}", path)),
+        None => io::stdout()
+            .write_all(contents)
+            .map_err(|_| "Write failed to stdout".to_string()),
+    }
+}
+
+fn disasm(args: &[String]) -> Result<(), String> {
+    let file_name = args.get(2).ok_or(USAGE)?;
+    let rom = read_file(file_name)?;
+
+    let quirks = parse_option(args, "--quirks")?.unwrap_or_default();
+
+    write_output(args, disassemble(&rom, &quirks).as_bytes())
+}
+
+fn asm(args: &[String]) -> Result<(), String> {
+    let file_name = args.get(2).ok_or(USAGE)?;
+    let source =
+        fs::read_to_string(file_name).map_err(|_| format!("Read failed from {}", file_name))?;
+    let assembly = assemble(&source).map_err(|error| format!("{}:{}", file_name, error))?;
+
+    if let Some(path) = option_value(args, "--symbols")? {
+        fs::write(path, assembly.symbol_map()).map_err(|_| format!("Write failed to {}", path))?;
+    }
+    write_output(args, &assembly.rom)
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    match args.get(1).map(String::as_str) {
+        Some("disasm") => disasm(args),
+        Some("asm") => asm(args),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    if let Err(message) = run(&args) {
+        eprintln!("{}", message);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,311 @@
+//! Command line helpers shared by the frontends.
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::keymap::KeyMap;
+use crate::palette::{parse_color, parse_palette, Palette, DEFAULT_PALETTE};
+use crate::program::Machine;
+use crate::quirks::Quirks;
+use crate::random::{CosmacVipRandom, RandomSource, SeededRandom};
+use crate::trace::{TraceFilter, TraceFormat, TraceWriter};
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 11;
+
+/// Usage text for the options read by `MachineOptions::from_args`.
+pub const MACHINE_OPTIONS_USAGE: &str =
+    "[--quirks original|vip|chip48|schip] [--ipf instructions-per-frame] [--seed seed | --vip-random --vip-interpreter file] \
+     [--trace file [--trace-format text|jsonl] [--trace-range start-end] [--trace-kind kinds]]";
+
+/// Usage text for the options read by `palette_from_args`.
+pub const PALETTE_OPTIONS_USAGE: &str =
+    "[--palette theme|background,plane1,plane2,both] [--background RRGGBB] [--foreground RRGGBB]";
+
+/// Usage text for the options read by `keymap_from_args`.
+pub const KEYMAP_OPTIONS_USAGE: &str = "[--keymap classic|numpad|legacy] [--bind key=hex,...]";
+
+/// Usage text for `args_with_config`.
+pub const CONFIG_USAGE: &str = "[--config file]";
+
+/// Turns the lines of a config file into command line options: `name = value` becomes
+/// `--name value` and a bare `name` becomes the flag `--name`.
+///
+/// Options following a `[file name]` line only apply to the ROM with that file name, and
+/// take precedence over the options before the first such line.
+///
+/// Blank lines and lines starting with `#` are ignored. Since colours may start with `#`,
+/// comments cannot follow a value.
+pub fn parse_config(config: &str, rom_file_name: Option<&str>) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut rom_args = Vec::new();
+    // whether the current section applies, or `None` before the first section
+    let mut section = None;
+
+    for (index, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .ok_or(format!("Line {}: expected [rom file name]", index + 1))?;
+            section = Some(Some(name) == rom_file_name);
+            continue;
+        }
+
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (line, None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("Line {}: expected name = value", index + 1));
+        }
+
+        let section_args = match section {
+            None => &mut args,
+            Some(true) => &mut rom_args,
+            Some(false) => continue,
+        };
+        section_args.push(format!("--{}", name));
+        section_args.extend(value.map(String::from));
+    }
+
+    rom_args.extend(args);
+    Ok(rom_args)
+}
+
+/// Appends the options of the file given with `--config` to the command line, so options
+/// given on the command line take precedence. Sections of the file apply to the ROM given
+/// as the first argument.
+pub fn args_with_config(args: Vec<String>) -> Result<Vec<String>, String> {
+    let path = match option_value(&args, "--config")? {
+        Some(path) => path.to_string(),
+        None => return Ok(args),
+    };
+    let rom_file_name = args
+        .get(1)
+        .and_then(|rom| Path::new(rom).file_name())
+        .and_then(|name| name.to_str());
+
+    let config = fs::read_to_string(&path).map_err(|_| format!("Read failed from {}", path))?;
+    let config_args =
+        parse_config(&config, rom_file_name).map_err(|message| format!("{}: {}", path, message))?;
+
+    Ok(args.into_iter().chain(config_args).collect())
+}
+
+/// Palette chosen with `--palette`, with `--background` and `--foreground` replacing the
+/// colours of the background and the first plane.
+pub fn palette_from_args(args: &[String]) -> Result<Palette, String> {
+    let mut palette = match option_value(args, "--palette")? {
+        Some(palette) => parse_palette(palette)?,
+        None => DEFAULT_PALETTE,
+    };
+
+    if let Some(color) = option_value(args, "--background")? {
+        palette[0] = parse_color(color)?;
+    }
+    if let Some(color) = option_value(args, "--foreground")? {
+        palette[1] = parse_color(color)?;
+    }
+
+    Ok(palette)
+}
+
+/// Key map chosen with `--keymap`, with the keys given with `--bind` bound on top of it.
+pub fn keymap_from_args(args: &[String]) -> Result<KeyMap, String> {
+    let mut keymap = match option_value(args, "--keymap")? {
+        Some(name) => KeyMap::preset(name)?,
+        None => KeyMap::default(),
+    };
+
+    if let Some(bindings) = option_value(args, "--bind")? {
+        keymap.bind_all(bindings)?;
+    }
+
+    Ok(keymap)
+}
+
+/// Value following `--name` on the command line, if the option is present.
+pub fn option_value<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>, String> {
+    match args.iter().position(|arg| arg == name) {
+        Some(index) => args
+            .get(index + 1)
+            .map(|value| Some(value.as_str()))
+            .ok_or(format!("{} requires a value", name)),
+        None => Ok(None),
+    }
+}
+
+/// Parses the value following `--name`, if the option is present.
+pub fn parse_option<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>, String> {
+    match option_value(args, name)? {
+        Some(value) => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| format!("Invalid value {} for {}", value, name)),
+        None => Ok(None),
+    }
+}
+
+pub fn has_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+/// How to build and drive a `Machine`, common to every frontend.
+pub struct MachineOptions {
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    /// Seed for CXNN, drawn from entropy when not given on the command line. The VIP
+    /// generator starts from its low 16 bits.
+    pub seed: u64,
+    /// Whether CXNN approximates the COSMAC VIP interpreter, see `CosmacVipRandom`.
+    pub vip_random: bool,
+    /// Dump of the VIP CHIP-8 interpreter, whose code `vip_random` draws numbers from.
+    pub vip_interpreter: Option<Vec<u8>>,
+    /// File receiving the execution trace, no tracing when absent.
+    pub trace_path: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+}
+
+impl MachineOptions {
+    pub fn from_args(args: &[String]) -> Result<MachineOptions, String> {
+        let quirks = match option_value(args, "--quirks")? {
+            Some(name) => name.parse::<Quirks>()?,
+            None => Quirks::default(),
+        };
+
+        Ok(MachineOptions {
+            quirks,
+            instructions_per_frame: parse_option(args, "--ipf")?
+                .unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME),
+            seed: parse_option(args, "--seed")?.unwrap_or_else(|| fastrand::u64(..)),
+            vip_random: has_flag(args, "--vip-random"),
+            vip_interpreter: option_value(args, "--vip-interpreter")?
+                .map(|path| fs::read(path).map_err(|_| format!("Read failed from {}", path)))
+                .transpose()?,
+            trace_path: option_value(args, "--trace")?.map(String::from),
+            trace_format: parse_option(args, "--trace-format")?.unwrap_or(TraceFormat::Text),
+            trace_filter: TraceFilter {
+                addresses: option_value(args, "--trace-range")?
+                    .map(TraceFilter::parse_address_range)
+                    .transpose()?,
+                instructions: option_value(args, "--trace-kind")?
+                    .map(|kinds| kinds.split(',').map(String::from).collect())
+                    .unwrap_or_default(),
+            },
+        })
+    }
+
+    pub fn random_source(&self) -> Result<Box<dyn RandomSource>, String> {
+        if !self.vip_random {
+            return Ok(Box::new(SeededRandom::new(self.seed)));
+        }
+
+        let interpreter = self.vip_interpreter.as_ref().ok_or(
+            "--vip-random needs --vip-interpreter with a dump of the VIP CHIP-8 interpreter",
+        )?;
+        Ok(Box::new(
+            CosmacVipRandom::new(interpreter)?.with_seed(self.seed as u16),
+        ))
+    }
+
+    pub fn load_machine(&self, file_name: &str) -> Result<Machine, String> {
+        let machine =
+            Machine::load(file_name, self.quirks)?.with_random_source(self.random_source()?);
+
+        match &self.trace_path {
+            Some(path) => {
+                let file = File::create(path).map_err(|_| format!("Write failed to {}", path))?;
+                let tracer = TraceWriter::new(
+                    BufWriter::new(file),
+                    self.trace_format,
+                    self.trace_filter.clone(),
+                );
+                Ok(machine.with_tracer(Box::new(tracer)))
+            }
+            None => Ok(machine),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_config_test() {
+        let config = "# display\nforeground = #33ff66\n\n  vip-random\nipf=20\n";
+
+        assert_eq!(
+            parse_config(config, None),
+            Ok(args(&[
+                "--foreground",
+                "#33ff66",
+                "--vip-random",
+                "--ipf",
+                "20"
+            ]))
+        );
+        assert!(parse_config("two words = 1", None).is_err());
+    }
+
+    #[test]
+    fn parse_config_sections_test() {
+        let config = "keymap = classic\n[pong.ch8]\nkeymap = numpad\n[ tetris.ch8 ]\nipf = 30\n";
+
+        assert_eq!(
+            parse_config(config, Some("pong.ch8")),
+            Ok(args(&["--keymap", "numpad", "--keymap", "classic"]))
+        );
+        assert_eq!(
+            parse_config(config, Some("tetris.ch8")),
+            Ok(args(&["--ipf", "30", "--keymap", "classic"]))
+        );
+        assert_eq!(
+            parse_config(config, None),
+            Ok(args(&["--keymap", "classic"]))
+        );
+        assert!(parse_config("[]\nipf = 30", None).is_err());
+    }
+
+    #[test]
+    fn vip_random_seed_test() {
+        let mut options =
+            MachineOptions::from_args(&args(&["rom", "--seed", "256", "--vip-random"])).unwrap();
+        assert!(options.random_source().is_err());
+
+        // a blank interpreter page leaves the high byte of the seed as the number
+        options.vip_interpreter = Some(vec![0; 0x200]);
+        assert_eq!(options.random_source().unwrap().next_u8(), 1);
+    }
+
+    #[test]
+    fn palette_from_args_test() {
+        let palette = palette_from_args(&args(&[
+            "rom",
+            "--palette",
+            "amber",
+            "--foreground",
+            "#00ff00",
+        ]))
+        .unwrap();
+
+        assert_eq!(palette[0], [0x14, 0x0c, 0x00]);
+        assert_eq!(palette[1], [0x00, 0xff, 0x00]);
+        assert_eq!(palette_from_args(&args(&["rom"])), Ok(DEFAULT_PALETTE));
+    }
+}
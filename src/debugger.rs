@@ -0,0 +1,430 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::MachineError;
+use crate::instruction::{instruction_name, parse_opcode, Instruction};
+use crate::program::{Machine, StepOutcome};
+use crate::quirks::IndexIncrement;
+
+/// Opcode match where every nibble written as a hex digit must be equal and any other
+/// character, such as the `x`, `y` and `n` of `Dxyn`, matches anything.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct OpcodePattern {
+    value: u16,
+    mask: u16,
+}
+
+impl OpcodePattern {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl FromStr for OpcodePattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<OpcodePattern, String> {
+        if pattern.chars().count() != 4 {
+            return Err(format!(
+                "Opcode pattern {} must be 4 characters long",
+                pattern
+            ));
+        }
+
+        let mut value = 0;
+        let mut mask = 0;
+        for character in pattern.chars() {
+            value <<= 4;
+            mask <<= 4;
+            if let Some(digit) = character.to_digit(16) {
+                value |= digit as u16;
+                mask |= 0xf;
+            }
+        }
+
+        Ok(OpcodePattern { value, mask })
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for shift in [12, 8, 4, 0] {
+            if (self.mask >> shift) & 0xf == 0 {
+                write!(f, "?")?;
+            } else {
+                write!(f, "{:X}", (self.value >> shift) & 0xf)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Breakpoint {
+    /// Stops before executing the instruction at this address.
+    Address(u16),
+    /// Stops before executing an opcode matching the pattern.
+    Opcode(OpcodePattern),
+    /// Stops before executing an `Instruction` variant, by name, such as `Draw`.
+    Instruction(String),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Address(address) => write!(f, "address {:#06x}", address),
+            Breakpoint::Opcode(pattern) => write!(f, "opcode {}", pattern),
+            Breakpoint::Instruction(name) => write!(f, "instruction {}", name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Watchpoint {
+    /// Stops after an instruction writes to this memory address.
+    Memory(u16),
+    /// Stops after an instruction writes to VX.
+    Register(u8),
+    /// Stops after an instruction writes to I.
+    I,
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watchpoint::Memory(address) => write!(f, "memory {:#06x}", address),
+            Watchpoint::Register(register) => write!(f, "register V{:X}", register),
+            Watchpoint::I => write!(f, "register I"),
+        }
+    }
+}
+
+/// Why execution stopped and control went back to the user.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum StopReason {
+    /// The requested step, step over or step out finished.
+    Stepped,
+    /// About to execute an instruction hitting the breakpoint at this index.
+    Breakpoint(usize),
+    /// The last instruction wrote to the watchpoint at this index.
+    Watchpoint(usize),
+    /// The program exited through 00FD.
+    Halted,
+    /// The instruction budget ran out.
+    Limit,
+    Error(MachineError),
+}
+
+/// Registers and memory an instruction writes to, worked out before it is executed.
+struct Writes {
+    registers: u16,
+    i: bool,
+    memory: Option<(usize, usize)>,
+}
+
+fn register_mask(from: u8, to: u8) -> u16 {
+    let (low, high) = if from <= to { (from, to) } else { (to, from) };
+    (low..=high).fold(0, |mask, register| mask | 1 << register)
+}
+
+fn writes(instruction: Instruction, machine: &Machine) -> Writes {
+    const VF: u16 = 1 << 0xf;
+
+    let quirks = machine.quirks();
+    let increments_i = quirks.load_store_increment != IndexIncrement::Unchanged;
+    let i = machine.i() as usize;
+
+    let mut writes = Writes {
+        registers: 0,
+        i: false,
+        memory: None,
+    };
+
+    match instruction {
+        Instruction::SetV { register, .. }
+        | Instruction::AddToRegister { register, .. }
+        | Instruction::SetRandomNumber { register, .. }
+        | Instruction::SetRegisterFromDelayTimer(register)
+        | Instruction::HaltAndGetKey(register) => writes.registers = 1 << register,
+        Instruction::StoreYToX { register_x, .. } => writes.registers = 1 << register_x,
+        Instruction::OrRegisters { register_x, .. }
+        | Instruction::AndRegisters { register_x, .. }
+        | Instruction::XorRegisters { register_x, .. } => {
+            writes.registers = 1 << register_x;
+            if quirks.logic_resets_vf {
+                writes.registers |= VF;
+            }
+        }
+        Instruction::AddRegisters { register_x, .. }
+        | Instruction::SubtractXMinusY { register_x, .. }
+        | Instruction::SubtractYMinusX { register_x, .. }
+        | Instruction::ShiftRegisterLeft { register_x, .. }
+        | Instruction::ShiftRegisterRight { register_x, .. } => {
+            writes.registers = 1 << register_x | VF
+        }
+        Instruction::Draw { .. } => writes.registers = VF,
+        Instruction::LoadRegisters(register) => {
+            writes.registers = register_mask(0, register);
+            writes.i = increments_i;
+        }
+        Instruction::LoadFlags(register) => writes.registers = register_mask(0, register),
+        Instruction::LoadRegisterRange {
+            register_x,
+            register_y,
+        } => writes.registers = register_mask(register_x, register_y),
+        Instruction::StoreAddrToI(_)
+        | Instruction::AddRegisterToI(_)
+        | Instruction::SetIToFontLocation(_)
+        | Instruction::SetIToBigFontLocation(_)
+        | Instruction::StoreNextWordToI => writes.i = true,
+        Instruction::SaveRegisters(register) => {
+            writes.memory = Some((i, register as usize + 1));
+            writes.i = increments_i;
+        }
+        Instruction::SaveRegisterRange {
+            register_x,
+            register_y,
+        } => writes.memory = Some((i, register_x.abs_diff(register_y) as usize + 1)),
+        Instruction::BinaryRepresentationFromRegister(_) => writes.memory = Some((i, 3)),
+        _ => {}
+    }
+
+    writes
+}
+
+/// Breakpoints, watchpoints and execution control on top of `Machine::step`.
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    instructions_per_frame: u32,
+    instructions_in_frame: u32,
+}
+
+impl Debugger {
+    /// `instructions_per_frame` decides how often the 60 Hz timers tick while stepping.
+    pub fn new(instructions_per_frame: u32) -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            instructions_per_frame: instructions_per_frame.max(1),
+            instructions_in_frame: 0,
+        }
+    }
+
+    /// Index of the first breakpoint hit by the instruction at the program counter.
+    pub fn breakpoint_at(&self, machine: &Machine) -> Option<usize> {
+        let address = machine.program_counter();
+        let opcode = machine.opcode_at(address)?;
+        let instruction = parse_opcode(opcode);
+
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| match breakpoint {
+                Breakpoint::Address(breakpoint_address) => *breakpoint_address == address,
+                Breakpoint::Opcode(pattern) => pattern.matches(opcode),
+                Breakpoint::Instruction(name) => instruction
+                    .map(|instruction| instruction_name(&instruction).eq_ignore_ascii_case(name))
+                    .unwrap_or(false),
+            })
+    }
+
+    /// Executes a single instruction, ending frames as `run_frame` would.
+    ///
+    /// Returns the index of the watchpoint the instruction wrote to, if any.
+    fn execute(&mut self, machine: &mut Machine) -> Result<Option<usize>, StopReason> {
+        let address = machine.program_counter();
+
+        loop {
+            let instruction = machine
+                .opcode_at(address)
+                .and_then(parse_opcode)
+                .map(|instruction| (instruction, writes(instruction, machine)));
+
+            let outcome = machine.step().map_err(StopReason::Error)?;
+
+            self.instructions_in_frame += 1;
+            if self.instructions_in_frame >= self.instructions_per_frame
+                || outcome == StepOutcome::WaitingForDisplay
+            {
+                self.instructions_in_frame = 0;
+                machine.end_frame();
+            }
+
+            match outcome {
+                StepOutcome::Halted => return Err(StopReason::Halted),
+                StepOutcome::WaitingForDisplay => continue,
+                StepOutcome::Executed(_) => {}
+            }
+
+            // FX0A repeats itself until a key is released, and writes nothing until then
+            let repeated = machine.program_counter() == address;
+            let writes = match instruction {
+                Some((_, writes)) if !repeated => writes,
+                _ => return Ok(None),
+            };
+
+            let hit = self
+                .watchpoints
+                .iter()
+                .position(|watchpoint| match watchpoint {
+                    Watchpoint::Register(register) => writes.registers & (1 << register) != 0,
+                    Watchpoint::I => writes.i,
+                    Watchpoint::Memory(target) => writes
+                        .memory
+                        .map(|(start, len)| (start..start + len).contains(&(*target as usize)))
+                        .unwrap_or(false),
+                });
+
+            return Ok(hit);
+        }
+    }
+
+    /// Runs up to `limit` instructions until `done` holds or a breakpoint, watchpoint, exit
+    /// or error stops execution. A breakpoint on the first instruction is ignored, so that
+    /// execution can resume from it.
+    fn run_until(
+        &mut self,
+        machine: &mut Machine,
+        limit: u64,
+        done: impl Fn(&Machine) -> bool,
+    ) -> StopReason {
+        for executed in 0..limit {
+            if executed > 0 {
+                if let Some(index) = self.breakpoint_at(machine) {
+                    return StopReason::Breakpoint(index);
+                }
+            }
+
+            match self.execute(machine) {
+                Err(reason) => return reason,
+                Ok(Some(index)) => return StopReason::Watchpoint(index),
+                Ok(None) => {}
+            }
+
+            if done(machine) {
+                return StopReason::Stepped;
+            }
+        }
+
+        StopReason::Limit
+    }
+
+    /// Executes exactly one instruction.
+    pub fn step(&mut self, machine: &mut Machine) -> StopReason {
+        self.run_until(machine, 1, |_| true)
+    }
+
+    /// Executes one instruction, running a whole subroutine when it is a 2NNN call.
+    pub fn step_over(&mut self, machine: &mut Machine, limit: u64) -> StopReason {
+        let is_call = matches!(
+            machine
+                .opcode_at(machine.program_counter())
+                .and_then(parse_opcode),
+            Some(Instruction::CallSubroutineAtAddress(_))
+        );
+        if !is_call {
+            return self.step(machine);
+        }
+
+        let depth = machine.stack().len();
+        self.run_until(machine, limit, |machine| machine.stack().len() <= depth)
+    }
+
+    /// Runs until the current subroutine returns.
+    pub fn step_out(&mut self, machine: &mut Machine, limit: u64) -> StopReason {
+        let depth = machine.stack().len();
+        self.run_until(machine, limit, |machine| machine.stack().len() < depth)
+    }
+
+    /// Runs until a breakpoint, watchpoint, exit or error.
+    pub fn resume(&mut self, machine: &mut Machine, limit: u64) -> StopReason {
+        self.run_until(machine, limit, |_| false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+
+    // 0x200: V0 = 1, call 0x208, V2 = 3, jump to self
+    // 0x208: V1 = 2, I = 0x300, save V0..V1, return
+    const ROM: [u8; 16] = [
+        0x60, 0x01, 0x22, 0x08, 0x62, 0x03, 0x12, 0x06, 0x61, 0x02, 0xa3, 0x00, 0xf1, 0x55, 0x00,
+        0xee,
+    ];
+
+    fn machine() -> Machine {
+        Machine::from_rom(&ROM, Quirks::super_chip()).unwrap()
+    }
+
+    #[test]
+    fn opcode_pattern_test() {
+        let pattern: OpcodePattern = "Dxyn".parse().unwrap();
+        assert!(pattern.matches(0xd01f));
+        assert!(!pattern.matches(0xa01f));
+        assert_eq!(pattern.to_string(), "D???");
+        assert!("D01".parse::<OpcodePattern>().is_err());
+    }
+
+    #[test]
+    fn breakpoint_test() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new(10);
+        debugger.breakpoints.push(Breakpoint::Address(0x20c));
+        debugger
+            .breakpoints
+            .push(Breakpoint::Instruction("returnfromsubroutine".to_string()));
+
+        assert_eq!(
+            debugger.resume(&mut machine, 100),
+            StopReason::Breakpoint(0)
+        );
+        assert_eq!(machine.program_counter(), 0x20c);
+
+        assert_eq!(
+            debugger.resume(&mut machine, 100),
+            StopReason::Breakpoint(1)
+        );
+        assert_eq!(machine.program_counter(), 0x20e);
+
+        assert_eq!(debugger.resume(&mut machine, 100), StopReason::Limit);
+    }
+
+    #[test]
+    fn watchpoint_test() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new(10);
+        debugger.watchpoints.push(Watchpoint::Memory(0x301));
+        debugger.watchpoints.push(Watchpoint::Register(0x2));
+
+        assert_eq!(
+            debugger.resume(&mut machine, 100),
+            StopReason::Watchpoint(0)
+        );
+        assert_eq!(machine.program_counter(), 0x20e);
+
+        assert_eq!(
+            debugger.resume(&mut machine, 100),
+            StopReason::Watchpoint(1)
+        );
+        assert_eq!(machine.registers()[0x2], 3);
+    }
+
+    #[test]
+    fn step_over_and_out_test() {
+        let mut machine = machine();
+        let mut debugger = Debugger::new(10);
+
+        assert_eq!(debugger.step(&mut machine), StopReason::Stepped);
+        assert_eq!(debugger.step_over(&mut machine, 100), StopReason::Stepped);
+        assert_eq!(machine.program_counter(), 0x204);
+        assert_eq!(machine.registers()[0x1], 2);
+
+        let mut machine = self::machine();
+        debugger.step(&mut machine);
+        debugger.step(&mut machine);
+        assert_eq!(machine.program_counter(), 0x208);
+        assert_eq!(debugger.step_out(&mut machine, 100), StopReason::Stepped);
+        assert_eq!(machine.program_counter(), 0x204);
+    }
+}
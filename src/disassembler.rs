@@ -0,0 +1,289 @@
+//! Turns a ROM into a listing by following control flow from the entry point.
+//!
+//! Bytes reached by jumps, calls, skips and fall through are decoded as instructions and
+//! everything else, including words `parse_opcode` does not know, is printed as data.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+use crate::instruction::{parse_opcode, Instruction};
+use crate::program::PROGRAM_STARTING_ADDRESS;
+use crate::quirks::Quirks;
+
+/// Offset of the first ROM byte in memory.
+const ROM_START: usize = PROGRAM_STARTING_ADDRESS as usize;
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+enum LabelKind {
+    /// Pointed to by I, usually a sprite.
+    Data,
+    /// Target of a jump.
+    Jump,
+    /// Target of a call.
+    Subroutine,
+}
+
+fn label_name(kind: LabelKind, address: u16) -> String {
+    let prefix = match kind {
+        LabelKind::Data => "data",
+        LabelKind::Jump => "label",
+        LabelKind::Subroutine => "sub",
+    };
+    format!("{}_{:03x}", prefix, address)
+}
+
+fn is_skip(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SkipIfEqual { .. }
+            | Instruction::SkipIfNotEqual { .. }
+            | Instruction::SkipIfRegistersEqual { .. }
+            | Instruction::SkipIfRegistersNotEqual { .. }
+            | Instruction::SkipIfPressedKeyContainsRegisterValue(_)
+            | Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(_)
+    )
+}
+
+/// Decoded instruction at an address, with the word following F000.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+struct Decoded {
+    instruction: Instruction,
+    opcode: u16,
+    long_address: Option<u16>,
+}
+
+impl Decoded {
+    fn len(&self) -> usize {
+        if self.long_address.is_some() {
+            4
+        } else {
+            2
+        }
+    }
+}
+
+/// The ROM as loaded in memory, split into instructions and labels.
+struct Analysis<'a> {
+    rom: &'a [u8],
+    instructions: BTreeMap<u16, Decoded>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl<'a> Analysis<'a> {
+    fn word_at(&self, address: u16) -> Option<u16> {
+        let offset = (address as usize).checked_sub(ROM_START)?;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn decode(&self, address: u16) -> Option<Decoded> {
+        let opcode = self.word_at(address)?;
+        let instruction = parse_opcode(opcode)?;
+        let long_address = match instruction {
+            Instruction::StoreNextWordToI => Some(self.word_at(address.wrapping_add(2))?),
+            _ => None,
+        };
+
+        Some(Decoded {
+            instruction,
+            opcode,
+            long_address,
+        })
+    }
+
+    fn add_label(&mut self, address: u16, kind: LabelKind) {
+        let label = self.labels.entry(address).or_insert(kind);
+        *label = (*label).max(kind);
+    }
+
+    fn new(rom: &'a [u8]) -> Analysis<'a> {
+        let mut analysis = Analysis {
+            rom,
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+
+        let mut pending = VecDeque::from([PROGRAM_STARTING_ADDRESS]);
+        while let Some(address) = pending.pop_front() {
+            if analysis.instructions.contains_key(&address) {
+                continue;
+            }
+
+            let decoded = match analysis.decode(address) {
+                Some(decoded) => decoded,
+                None => continue,
+            };
+            analysis.instructions.insert(address, decoded);
+
+            let next = address.wrapping_add(decoded.len() as u16);
+            match decoded.instruction {
+                Instruction::JumpToAddress(target) => {
+                    analysis.add_label(target, LabelKind::Jump);
+                    pending.push_back(target);
+                }
+                Instruction::JumpWithOffset(target) => {
+                    // Only the start of the jump table is known
+                    analysis.add_label(target, LabelKind::Jump);
+                    pending.push_back(target);
+                }
+                Instruction::CallSubroutineAtAddress(target) => {
+                    analysis.add_label(target, LabelKind::Subroutine);
+                    pending.push_back(target);
+                    pending.push_back(next);
+                }
+                Instruction::ReturnFromSubroutine | Instruction::Exit => {}
+                Instruction::StoreAddrToI(target) => {
+                    analysis.add_label(target, LabelKind::Data);
+                    pending.push_back(next);
+                }
+                Instruction::StoreNextWordToI => {
+                    analysis.add_label(decoded.long_address.unwrap_or(0), LabelKind::Data);
+                    pending.push_back(next);
+                }
+                instruction if is_skip(instruction) => {
+                    // Skips jump over a whole F000 NNNN
+                    let skipped_len = match analysis.word_at(next) {
+                        Some(0xf000) => 4,
+                        _ => 2,
+                    };
+                    pending.push_back(next);
+                    pending.push_back(next.wrapping_add(skipped_len));
+                }
+                _ => pending.push_back(next),
+            }
+        }
+
+        analysis
+    }
+
+    fn address_name(&self, address: u16) -> String {
+        match self.labels.get(&address) {
+            Some(kind) if self.is_in_rom(address) => label_name(*kind, address),
+            _ => format!("0x{:03X}", address),
+        }
+    }
+
+    fn is_in_rom(&self, address: u16) -> bool {
+        (ROM_START..ROM_START + self.rom.len()).contains(&(address as usize))
+    }
+}
+
+/// Disassembles a ROM loaded at 0x200 into a listing with one instruction or a run of data
+/// bytes per line, each preceded by its address and raw bytes. `quirks` decide how the
+/// instructions whose operands depend on them are written.
+pub fn disassemble(rom: &[u8], quirks: &Quirks) -> String {
+    let analysis = Analysis::new(rom);
+    let mut listing = String::new();
+    let mut data: Vec<u8> = Vec::new();
+    let mut data_address = 0;
+
+    let flush_data = |listing: &mut String, data: &mut Vec<u8>, address: usize| {
+        if data.is_empty() {
+            return;
+        }
+        let bytes: Vec<String> = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let values: Vec<String> = data.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        writeln!(
+            listing,
+            "    {:03X}  {:<24}DB {}",
+            address,
+            bytes.join(" "),
+            values.join(", ")
+        )
+        .unwrap();
+        data.clear();
+    };
+
+    let end = ROM_START + rom.len();
+    let mut address = ROM_START;
+    while address < end {
+        let label = analysis.labels.get(&(address as u16));
+        let instruction = analysis.instructions.get(&(address as u16));
+
+        if label.is_some() || instruction.is_some() || data.len() == DATA_BYTES_PER_LINE {
+            flush_data(&mut listing, &mut data, data_address);
+        }
+        if let Some(kind) = label {
+            writeln!(listing, "{}:", label_name(*kind, address as u16)).unwrap();
+        }
+
+        match instruction {
+            Some(decoded) => {
+                let mut text = decoded
+                    .instruction
+                    .mnemonic(quirks, |target| analysis.address_name(target));
+                let mut bytes = format!("{:04X}", decoded.opcode);
+                if let Some(long_address) = decoded.long_address {
+                    write!(text, " {}", analysis.address_name(long_address)).unwrap();
+                    write!(bytes, " {:04X}", long_address).unwrap();
+                }
+                writeln!(listing, "    {:03X}  {:<24}{}", address, bytes, text).unwrap();
+                address += decoded.len();
+            }
+            None => {
+                if data.is_empty() {
+                    data_address = address;
+                }
+                data.push(rom[address - ROM_START]);
+                address += 1;
+            }
+        }
+    }
+    flush_data(&mut listing, &mut data, data_address);
+
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_test() {
+        let rom = [
+            0x00, 0xe0, // CLS
+            0xa2, 0x10, // LD I, data_210
+            0x22, 0x0a, // CALL sub_20a
+            0x12, 0x06, // JP label_206
+            0xff, 0xff, // unreachable and undecodable
+            0x3a, 0x01, // SE VA, 0x01
+            0xd0, 0x15, // DRW V0, V1, 5
+            0x00, 0xee, // RET
+            0xf0, 0x90, // sprite
+        ];
+
+        assert_eq!(
+            disassemble(&rom, &Quirks::default()),
+            "    200  00E0                    CLS
+    202  A210                    LD I, data_210
+    204  220A                    CALL sub_20a
+label_206:
+    206  1206                    JP label_206
+    208  FF FF                   DB 0xFF, 0xFF
+sub_20a:
+    20A  3A01                    SE VA, 0x01
+    20C  D015                    DRW V0, V1, 5
+    20E  00EE                    RET
+data_210:
+    210  F0 90                   DB 0xF0, 0x90
+"
+        );
+    }
+
+    #[test]
+    fn long_i_load_test() {
+        let rom = [0xf0, 0x00, 0x02, 0x06, 0x12, 0x04, 0x3c];
+
+        assert_eq!(
+            disassemble(&rom, &Quirks::default()),
+            "    200  F000 0206               LD I, LONG data_206
+label_204:
+    204  1204                    JP label_204
+data_206:
+    206  3C                      DB 0x3C
+"
+        );
+    }
+}
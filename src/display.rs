@@ -0,0 +1,148 @@
+use std::iter;
+
+pub const NUM_ROWS: usize = 32;
+pub const NUM_COLS: usize = 64;
+
+pub const HIRES_NUM_ROWS: usize = 64;
+pub const HIRES_NUM_COLS: usize = 128;
+
+/// Plane mask selecting the first bitplane, the only one used outside XO-CHIP.
+pub const FIRST_PLANE: u8 = 0b01;
+
+/// Frame buffer at the currently active resolution, either 64x32 or 128x64.
+///
+/// Each pixel holds one bit per XO-CHIP bitplane, so its value is a colour index from 0 to 3.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PixelBuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl PixelBuffer {
+    pub fn new(width: usize, height: usize) -> PixelBuffer {
+        PixelBuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    /// Rebuilds a buffer from pixels laid out row by row, if their count matches the size.
+    pub(crate) fn from_pixels(width: usize, height: usize, pixels: Vec<u8>) -> Option<PixelBuffer> {
+        if pixels.len() != width * height {
+            return None;
+        }
+
+        Some(PixelBuffer {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn lowres() -> PixelBuffer {
+        PixelBuffer::new(NUM_COLS, NUM_ROWS)
+    }
+
+    pub fn hires() -> PixelBuffer {
+        PixelBuffer::new(HIRES_NUM_COLS, HIRES_NUM_ROWS)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_NUM_COLS
+    }
+
+    /// Colour index of a pixel, with bit 0 set by the first plane and bit 1 by the second.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        self.get(x, y) != 0
+    }
+
+    /// Colour indices of every pixel, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    /// Colour indices of every pixel, row by row, with each pixel repeated as a `scale` by
+    /// `scale` square.
+    pub fn scaled_pixels(&self, scale: usize) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.pixels.len() * scale * scale);
+
+        for row in self.rows() {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|pixel| iter::repeat_n(*pixel, scale))
+                .collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+
+        pixels
+    }
+
+    /// Clears the given planes of every pixel.
+    pub fn clear(&mut self, planes: u8) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel &= !planes);
+    }
+
+    /// XORs the given plane of a pixel, returning whether a lit pixel was turned off.
+    pub(crate) fn toggle(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let pixel = &mut self.pixels[y * self.width + x];
+        let collided = *pixel & plane != 0;
+        *pixel ^= plane;
+        collided
+    }
+
+    /// Moves the given planes by `dx` columns and `dy` rows, filling uncovered pixels with 0.
+    fn shift(&mut self, dx: isize, dy: isize, planes: u8) {
+        let source = self.pixels.clone();
+        let (width, height) = (self.width as isize, self.height as isize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&source_x) && (0..height).contains(&source_y) {
+                    source[(source_y * width + source_x) as usize] & planes
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.pixels[(y * width + x) as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+    }
+
+    pub(crate) fn scroll_down(&mut self, rows: usize, planes: u8) {
+        self.shift(0, rows as isize, planes);
+    }
+
+    pub(crate) fn scroll_up(&mut self, rows: usize, planes: u8) {
+        self.shift(0, -(rows as isize), planes);
+    }
+
+    pub(crate) fn scroll_right(&mut self, cols: usize, planes: u8) {
+        self.shift(cols as isize, 0, planes);
+    }
+
+    pub(crate) fn scroll_left(&mut self, cols: usize, planes: u8) {
+        self.shift(-(cols as isize), 0, planes);
+    }
+}
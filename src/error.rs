@@ -0,0 +1,84 @@
+use std::{error::Error, fmt};
+
+/// A fault raised while executing the instruction at `address`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MachineError {
+    /// The opcode does not decode to any supported instruction.
+    UnknownOpcode { address: u16, opcode: u16 },
+
+    /// 2NNN was executed with the call stack already full.
+    StackOverflow { address: u16, opcode: u16 },
+
+    /// 00EE was executed with an empty call stack.
+    StackUnderflow { address: u16, opcode: u16 },
+
+    /// The instruction tried to access `accessed` bytes starting at `target`, past the end of memory.
+    MemoryOutOfRange {
+        address: u16,
+        opcode: u16,
+        target: usize,
+        accessed: usize,
+    },
+
+    /// The program counter points at a location where no full opcode can be fetched.
+    ProgramCounterOutOfRange { address: u16 },
+}
+
+impl MachineError {
+    /// Address of the faulting instruction.
+    pub fn address(&self) -> u16 {
+        match *self {
+            MachineError::UnknownOpcode { address, .. }
+            | MachineError::StackOverflow { address, .. }
+            | MachineError::StackUnderflow { address, .. }
+            | MachineError::MemoryOutOfRange { address, .. }
+            | MachineError::ProgramCounterOutOfRange { address } => address,
+        }
+    }
+
+    /// The faulting opcode, unless it could not be fetched.
+    pub fn opcode(&self) -> Option<u16> {
+        match *self {
+            MachineError::UnknownOpcode { opcode, .. }
+            | MachineError::StackOverflow { opcode, .. }
+            | MachineError::StackUnderflow { opcode, .. }
+            | MachineError::MemoryOutOfRange { opcode, .. } => Some(opcode),
+            MachineError::ProgramCounterOutOfRange { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MachineError::UnknownOpcode { address, opcode } => {
+                write!(f, "Unknown opcode {:#06x} at {:#06x}", opcode, address)
+            }
+            MachineError::StackOverflow { address, opcode } => write!(
+                f,
+                "Stack overflow calling a subroutine with {:#06x} at {:#06x}",
+                opcode, address
+            ),
+            MachineError::StackUnderflow { address, opcode } => write!(
+                f,
+                "Returning from subroutine with {:#06x} at {:#06x}, but the stack is empty",
+                opcode, address
+            ),
+            MachineError::MemoryOutOfRange {
+                address,
+                opcode,
+                target,
+                accessed,
+            } => write!(
+                f,
+                "Opcode {:#06x} at {:#06x} accessed {} bytes at {:#06x}, outside of memory",
+                opcode, address, accessed, target
+            ),
+            MachineError::ProgramCounterOutOfRange { address } => {
+                write!(f, "Program counter {:#06x} is outside of memory", address)
+            }
+        }
+    }
+}
+
+impl Error for MachineError {}
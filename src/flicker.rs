@@ -0,0 +1,212 @@
+//! Reduces the flicker of sprites erased and redrawn with XOR every frame.
+//!
+//! A `FlickerFilter` turns each frame into the one to present. Its pixels index the colours
+//! returned by `FlickerFilter::colors`, which start with the four palette colours and go on
+//! with the fading shades of phosphor decay.
+
+use std::str::FromStr;
+
+use crate::display::PixelBuffer;
+use crate::palette::Palette;
+
+/// Longest phosphor decay, which keeps every shade within a 256 colour GIF palette.
+pub const MAX_DECAY_FRAMES: u8 = 64;
+
+const DEFAULT_DECAY_FRAMES: u8 = 4;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum AntiFlicker {
+    /// Frames are presented as they are.
+    #[default]
+    Off,
+    /// Each frame is combined with the previous one, so a pixel lit in either is lit.
+    Blend,
+    /// Pixels that turn off fade to the background over this many frames.
+    Phosphor(u8),
+}
+
+impl FromStr for AntiFlicker {
+    type Err = String;
+
+    /// Parses `off`, `blend`, `phosphor` or `phosphor:frames`.
+    fn from_str(mode: &str) -> Result<AntiFlicker, String> {
+        let (name, frames) = match mode.split_once(':') {
+            Some((name, frames)) => (name, Some(frames)),
+            None => (mode, None),
+        };
+
+        match (name, frames) {
+            ("off", None) => Ok(AntiFlicker::Off),
+            ("blend", None) => Ok(AntiFlicker::Blend),
+            ("phosphor", None) => Ok(AntiFlicker::Phosphor(DEFAULT_DECAY_FRAMES)),
+            ("phosphor", Some(frames)) => frames
+                .parse::<u8>()
+                .ok()
+                .filter(|frames| (1..=MAX_DECAY_FRAMES).contains(frames))
+                .map(AntiFlicker::Phosphor)
+                .ok_or(format!(
+                    "Phosphor decay must last from 1 to {} frames",
+                    MAX_DECAY_FRAMES
+                )),
+            _ => Err(format!(
+                "Unknown anti-flicker mode {}, expected off, blend or phosphor[:frames]",
+                mode
+            )),
+        }
+    }
+}
+
+/// Remembers the frames needed by an `AntiFlicker` mode.
+pub struct FlickerFilter {
+    mode: AntiFlicker,
+    previous: Option<PixelBuffer>,
+    /// Colour index each pixel was last lit with, 0 once it has faded out.
+    faded_colors: Vec<u8>,
+    /// Frames since each pixel was last lit.
+    ages: Vec<u8>,
+}
+
+impl FlickerFilter {
+    pub fn new(mode: AntiFlicker) -> FlickerFilter {
+        FlickerFilter {
+            mode,
+            previous: None,
+            faded_colors: Vec::new(),
+            ages: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> AntiFlicker {
+        self.mode
+    }
+
+    /// Frame to present for `frame`, which must be given once for every 60 Hz frame.
+    ///
+    /// Changing resolution starts over from the new frame.
+    pub fn apply(&mut self, frame: &PixelBuffer) -> PixelBuffer {
+        let resized = self
+            .previous
+            .as_ref()
+            .map(|previous| previous.width() != frame.width())
+            .unwrap_or(true);
+        if resized {
+            self.faded_colors = vec![0; frame.pixels().len()];
+            self.ages = vec![0; frame.pixels().len()];
+        }
+
+        let presented = match (self.mode, &self.previous) {
+            (AntiFlicker::Off, _) => frame.clone(),
+            (AntiFlicker::Blend, Some(previous)) if !resized => {
+                let pixels = frame
+                    .pixels()
+                    .iter()
+                    .zip(previous.pixels())
+                    .map(|(pixel, previous)| pixel | previous)
+                    .collect();
+                PixelBuffer::from_pixels(frame.width(), frame.height(), pixels).unwrap()
+            }
+            (AntiFlicker::Blend, _) => frame.clone(),
+            (AntiFlicker::Phosphor(frames), _) => self.decay(frame, frames),
+        };
+
+        self.previous = Some(frame.clone());
+        presented
+    }
+
+    fn decay(&mut self, frame: &PixelBuffer, frames: u8) -> PixelBuffer {
+        let mut pixels = Vec::with_capacity(frame.pixels().len());
+
+        for (index, pixel) in frame.pixels().iter().enumerate() {
+            let (color, age) = (&mut self.faded_colors[index], &mut self.ages[index]);
+            if *pixel != 0 {
+                *color = *pixel;
+                *age = 0;
+                pixels.push(*pixel);
+                continue;
+            }
+
+            *age = age.saturating_add(1);
+            if *color == 0 || *age >= frames {
+                *color = 0;
+                pixels.push(0);
+            } else {
+                pixels.push(shade_index(*color, *age, frames));
+            }
+        }
+
+        PixelBuffer::from_pixels(frame.width(), frame.height(), pixels).unwrap()
+    }
+
+    /// Colours indexed by the presented frames: the palette followed by the shades of every
+    /// lit colour fading towards the background.
+    pub fn colors(&self, palette: &Palette) -> Vec<[u8; 3]> {
+        let mut colors = palette.to_vec();
+
+        if let AntiFlicker::Phosphor(frames) = self.mode {
+            for color in 1..palette.len() {
+                for age in 1..frames {
+                    let lit = 1.0 - age as f32 / frames as f32;
+                    let mut shade = [0; 3];
+                    for channel in 0..3 {
+                        let (from, to) =
+                            (palette[color][channel] as f32, palette[0][channel] as f32);
+                        shade[channel] = (to + (from - to) * lit).round() as u8;
+                    }
+                    colors.push(shade);
+                }
+            }
+        }
+
+        colors
+    }
+}
+
+/// Index of the shade of `color` after `age` frames of a decay lasting `frames` frames.
+fn shade_index(color: u8, age: u8, frames: u8) -> u8 {
+    4 + (color - 1) * (frames - 1) + (age - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::DEFAULT_PALETTE;
+
+    fn frame(pixels: [u8; 2]) -> PixelBuffer {
+        PixelBuffer::from_pixels(2, 1, pixels.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn blend_test() {
+        let mut filter = FlickerFilter::new(AntiFlicker::Blend);
+
+        assert_eq!(filter.apply(&frame([1, 0])).pixels(), [1, 0]);
+        assert_eq!(filter.apply(&frame([0, 2])).pixels(), [1, 2]);
+        assert_eq!(filter.apply(&frame([0, 0])).pixels(), [0, 2]);
+        assert_eq!(filter.colors(&DEFAULT_PALETTE), DEFAULT_PALETTE.to_vec());
+    }
+
+    #[test]
+    fn phosphor_test() {
+        let mut filter = FlickerFilter::new(AntiFlicker::Phosphor(3));
+        let colors = filter.colors(&DEFAULT_PALETTE);
+
+        assert_eq!(filter.apply(&frame([1, 2])).pixels(), [1, 2]);
+        let presented = filter.apply(&frame([0, 0]));
+        assert_eq!(presented.pixels(), [4, 6]);
+        assert_eq!(colors[presented.get(0, 0) as usize], [170, 170, 170]);
+        assert_eq!(filter.apply(&frame([0, 0])).pixels(), [5, 7]);
+        assert_eq!(colors[5], [85, 85, 85]);
+        assert_eq!(filter.apply(&frame([0, 1])).pixels(), [0, 1]);
+
+        assert_eq!(colors.len(), 4 + 3 * 2);
+    }
+
+    #[test]
+    fn parse_test() {
+        assert_eq!("phosphor".parse(), Ok(AntiFlicker::Phosphor(4)));
+        assert_eq!("phosphor:10".parse(), Ok(AntiFlicker::Phosphor(10)));
+        assert_eq!("blend".parse(), Ok(AntiFlicker::Blend));
+        assert!("phosphor:0".parse::<AntiFlicker>().is_err());
+        assert!("blur".parse::<AntiFlicker>().is_err());
+    }
+}
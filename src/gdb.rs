@@ -0,0 +1,538 @@
+//! GDB Remote Serial Protocol stub driving a `Machine` through a `Debugger`.
+//!
+//! Registers are numbered V0-VF (0-15, 8 bits), I (16, 16 bits), PC (17, 16 bits),
+//! SP (18, the stack depth, 8 bits), DT (19, 8 bits) and ST (20, 8 bits), sent little endian
+//! and described to the client through `qXfer:features:read:target.xml`. Registers are read
+//! only. Breakpoints are kept by the stub rather than patched into memory, and `Z2` write
+//! watchpoints map to memory watchpoints.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::ops::Range;
+
+use crate::debugger::{Breakpoint, Debugger, StopReason, Watchpoint};
+use crate::error::MachineError;
+use crate::program::{Machine, MEMORY_SIZE};
+
+/// Instructions run between checks for an interrupt from the client while continuing.
+const INSTRUCTIONS_PER_POLL: u64 = 10_000;
+
+const INTERRUPT: u8 = 0x03;
+
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+const REGISTER_DT: usize = 19;
+const REGISTER_ST: usize = 20;
+
+/// Byte stream to the client, with a way to poll it for interrupts without blocking.
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parses the `addr,len` arguments shared by memory and breakpoint packets.
+fn parse_address_and_len(arguments: &str) -> Option<(u16, usize)> {
+    let (address, len) = arguments.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Addresses of `len` bytes from `address`, `None` when they run past the end of memory.
+fn memory_range(address: u16, len: usize) -> Option<Range<usize>> {
+    let start = address as usize;
+    let end = start.checked_add(len).filter(|end| *end <= MEMORY_SIZE)?;
+    Some(start..end)
+}
+
+fn target_description() -> String {
+    let mut registers = String::new();
+    for register in 0..16 {
+        write!(
+            registers,
+            r#"<reg name="v{:x}" bitsize="8" type="uint8"/>"#,
+            register
+        )
+        .unwrap();
+    }
+
+    format!(
+        r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0"><feature name="org.chip8.core">{}<reg name="i" bitsize="16" type="data_ptr"/><reg name="pc" bitsize="16" type="code_ptr"/><reg name="sp" bitsize="8" type="uint8"/><reg name="dt" bitsize="8" type="uint8"/><reg name="st" bitsize="8" type="uint8"/></feature></target>"#,
+        registers
+    )
+}
+
+fn register_bytes(machine: &Machine, register: usize) -> Option<Vec<u8>> {
+    let bytes = match register {
+        0..=15 => vec![machine.registers()[register]],
+        REGISTER_I => machine.i().to_le_bytes().to_vec(),
+        REGISTER_PC => machine.program_counter().to_le_bytes().to_vec(),
+        REGISTER_SP => vec![machine.stack().len() as u8],
+        REGISTER_DT => vec![machine.delay_timer()],
+        REGISTER_ST => vec![machine.sound_timer()],
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+}
+
+/// Serves one client connection.
+pub struct GdbStub<C: Connection> {
+    connection: C,
+    debugger: Debugger,
+    /// Bytes read while polling for interrupts, handled before reading the connection again.
+    pending: VecDeque<u8>,
+    acknowledge: bool,
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(connection: C, debugger: Debugger) -> GdbStub<C> {
+        GdbStub {
+            connection,
+            debugger,
+            pending: VecDeque::new(),
+            acknowledge: true,
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+
+        let mut byte = [0];
+        loop {
+            match self.connection.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Reads the next packet or interrupt, `None` once the client disconnects.
+    fn read_incoming(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Incoming::Interrupt)),
+                Some(b'$') => {}
+                // Acknowledgements of our replies and line noise
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+
+            let mut sum = [0; 2];
+            for digit in sum.iter_mut() {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let valid = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+                == Some(checksum(&data));
+
+            if self.acknowledge {
+                self.connection.write_all(if valid { b"+" } else { b"-" })?;
+                self.connection.flush()?;
+            }
+            if valid {
+                return Ok(Some(Incoming::Packet(data)));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data));
+
+        loop {
+            self.connection.write_all(packet.as_bytes())?;
+            self.connection.flush()?;
+            if !self.acknowledge {
+                return Ok(());
+            }
+
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                Some(b'+') | None => return Ok(()),
+                // The client went ahead without acknowledging, keep what it sent
+                Some(byte) => {
+                    self.pending.push_front(byte);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Whether the client sent an interrupt, without waiting for one.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.connection.set_nonblocking(true)?;
+        let mut buffer = [0; 64];
+        let result = self.connection.read(&mut buffer);
+        self.connection.set_nonblocking(false)?;
+
+        match result {
+            Ok(len) => {
+                let received = &buffer[..len];
+                let interrupted = received.contains(&INTERRUPT);
+                self.pending
+                    .extend(received.iter().filter(|byte| **byte != INTERRUPT));
+                Ok(interrupted)
+            }
+            Err(error)
+                if error.kind() == ErrorKind::WouldBlock
+                    || error.kind() == ErrorKind::Interrupted =>
+            {
+                Ok(false)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn stop_reply(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Watchpoint(index) => match self.debugger.watchpoints[index] {
+                Watchpoint::Memory(address) => format!("T05watch:{:x};", address),
+                _ => "S05".to_string(),
+            },
+            StopReason::Halted => "W00".to_string(),
+            StopReason::Error(MachineError::UnknownOpcode { .. }) => "S04".to_string(),
+            StopReason::Error(_) => "S0b".to_string(),
+            StopReason::Stepped | StopReason::Breakpoint(_) | StopReason::Limit => {
+                "S05".to_string()
+            }
+        }
+    }
+
+    /// Runs until a breakpoint, watchpoint, exit, error or an interrupt from the client.
+    fn resume(&mut self, machine: &mut Machine) -> io::Result<String> {
+        let mut reason = self.debugger.resume(machine, INSTRUCTIONS_PER_POLL);
+
+        while reason == StopReason::Limit {
+            if self.interrupted()? {
+                return Ok("S02".to_string());
+            }
+            // Each resume skips the breakpoint it starts on
+            if self.debugger.breakpoint_at(machine).is_some() {
+                return Ok("S05".to_string());
+            }
+            reason = self.debugger.resume(machine, INSTRUCTIONS_PER_POLL);
+        }
+
+        Ok(self.stop_reply(reason))
+    }
+
+    fn update_breakpoint(&mut self, packet: &str) -> Option<&'static str> {
+        let insert = packet.starts_with('Z');
+        let mut fields = packet[1..].splitn(2, ',');
+        let kind = fields.next()?;
+        let (address, len) = parse_address_and_len(fields.next()?)?;
+
+        match kind {
+            "0" | "1" => {
+                let breakpoint = Breakpoint::Address(address);
+                self.debugger
+                    .breakpoints
+                    .retain(|existing| *existing != breakpoint);
+                if insert {
+                    self.debugger.breakpoints.push(breakpoint);
+                }
+            }
+            "2" => {
+                let watched = memory_range(address, len.max(1))?;
+                self.debugger.watchpoints.retain(|existing| match existing {
+                    Watchpoint::Memory(address) => !watched.contains(&(*address as usize)),
+                    _ => true,
+                });
+                if insert {
+                    for address in watched {
+                        self.debugger
+                            .watchpoints
+                            .push(Watchpoint::Memory(address as u16));
+                    }
+                }
+            }
+            _ => return Some(""),
+        }
+
+        Some("OK")
+    }
+
+    /// Reply to a packet, `None` when the session ends.
+    fn handle(&mut self, machine: &mut Machine, packet: &str) -> io::Result<Option<String>> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => (0..=REGISTER_ST)
+                .filter_map(|register| register_bytes(machine, register))
+                .map(|bytes| hex_bytes(&bytes))
+                .collect(),
+            Some(b'p') => usize::from_str_radix(&packet[1..], 16)
+                .ok()
+                .and_then(|register| register_bytes(machine, register))
+                .map(|bytes| hex_bytes(&bytes))
+                .unwrap_or_else(|| "E01".to_string()),
+            Some(b'm') => parse_address_and_len(&packet[1..])
+                .and_then(|(address, len)| machine.memory().get(memory_range(address, len)?))
+                .map(hex_bytes)
+                .unwrap_or_else(|| "E01".to_string()),
+            Some(b'M') => packet[1..]
+                .split_once(':')
+                .and_then(|(arguments, data)| {
+                    let (address, len) = parse_address_and_len(arguments)?;
+                    let bytes = parse_hex_bytes(data).filter(|bytes| bytes.len() == len)?;
+                    machine.write_memory(address, &bytes).ok()
+                })
+                .map(|_| "OK".to_string())
+                .unwrap_or_else(|| "E01".to_string()),
+            Some(b'Z' | b'z') => self.update_breakpoint(packet).unwrap_or("E01").to_string(),
+            Some(b's') => {
+                let reason = self.debugger.step(machine);
+                self.stop_reply(reason)
+            }
+            Some(b'c') => self.resume(machine)?,
+            Some(b'H') => "OK".to_string(),
+            Some(b'D') => {
+                self.send("OK")?;
+                return Ok(None);
+            }
+            Some(b'k') => return Ok(None),
+            _ => self.handle_query(packet),
+        };
+
+        Ok(Some(reply))
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if packet == "QStartNoAckMode" {
+            self.acknowledge = false;
+            return "OK".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let description = target_description();
+            return match parse_address_and_len(range) {
+                Some((offset, len)) => {
+                    let chunk: String = description
+                        .chars()
+                        .skip(offset as usize)
+                        .take(len)
+                        .collect();
+                    let done = offset as usize + chunk.len() >= description.len();
+                    format!("{}{}", if done { 'l' } else { 'm' }, chunk)
+                }
+                None => "E01".to_string(),
+            };
+        }
+
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Answers packets until the client detaches, kills the session or disconnects.
+    pub fn serve(&mut self, machine: &mut Machine) -> io::Result<()> {
+        while let Some(incoming) = self.read_incoming()? {
+            let reply = match incoming {
+                // Nothing runs between packets, so the machine is already stopped
+                Incoming::Interrupt => "S02".to_string(),
+                Incoming::Packet(packet) => match self.handle(machine, &packet)? {
+                    Some(reply) => reply,
+                    None => return Ok(()),
+                },
+            };
+            self.send(&reply)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use std::io::Cursor;
+
+    struct MockConnection {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockConnection {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buffer)
+        }
+    }
+
+    impl Write for MockConnection {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.output.write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for MockConnection {
+        fn set_nonblocking(&self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum(data))
+    }
+
+    /// Runs a session without acknowledgements and returns the replies.
+    fn session(machine: &mut Machine, packets: &[&str]) -> Vec<String> {
+        let mut input = packet("QStartNoAckMode");
+        input.push('+');
+        for data in packets {
+            input.push_str(&packet(data));
+        }
+
+        let connection = MockConnection {
+            input: Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        };
+        let mut stub = GdbStub::new(connection, Debugger::new(10));
+        stub.serve(machine).unwrap();
+
+        let output = String::from_utf8(stub.connection.output).unwrap();
+        output
+            .trim_start_matches('+')
+            .split('$')
+            .skip(2)
+            .map(|reply| {
+                let (data, sum) = reply.split_once('#').unwrap();
+                assert_eq!(sum, format!("{:02x}", checksum(data)));
+                data.to_string()
+            })
+            .collect()
+    }
+
+    // 0x200: V0 = 1, call 0x208, V2 = 3, jump to self
+    // 0x208: V1 = 2, I = 0x300, save V0..V1, return
+    const ROM: [u8; 16] = [
+        0x60, 0x01, 0x22, 0x08, 0x62, 0x03, 0x12, 0x06, 0x61, 0x02, 0xa3, 0x00, 0xf1, 0x55, 0x00,
+        0xee,
+    ];
+
+    #[test]
+    fn session_test() {
+        let mut machine = Machine::from_rom(&ROM, Quirks::super_chip()).unwrap();
+
+        let replies = session(
+            &mut machine,
+            &[
+                "Z0,20c,2",
+                "Z2,301,1",
+                "c",
+                "p11",
+                "p12",
+                "c",
+                "m300,2",
+                "M300,2:abcd",
+                "m300,2",
+                "z2,301,1",
+                "s",
+                "g",
+                "mfff0,20",
+                "m0,ffffffffffffffff",
+                "Z2,0,ffffffffffffffff",
+                "vMustReplyEmpty",
+                "D",
+            ],
+        );
+
+        assert_eq!(
+            replies,
+            vec![
+                "OK",
+                "OK",
+                "S05",
+                "0c02",
+                "01",
+                "T05watch:301;",
+                "0102",
+                "OK",
+                "abcd",
+                "OK",
+                "S05",
+                "0102000000000000000000000000000000030402000000",
+                "E01",
+                "E01",
+                "E01",
+                "",
+                "OK",
+            ]
+        );
+        assert_eq!(machine.memory()[0x300], 0xab);
+    }
+
+    #[test]
+    fn target_description_test() {
+        let mut machine = Machine::from_rom(&ROM, Quirks::super_chip()).unwrap();
+        let replies = session(&mut machine, &["qXfer:features:read:target.xml:0,10"]);
+
+        assert_eq!(replies, vec!["m<?xml version=\"1"]);
+    }
+}
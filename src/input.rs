@@ -0,0 +1,120 @@
+use std::fmt;
+
+use crate::program::Machine;
+
+/// A keypad key going down or up at the start of a frame.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+impl KeyEvent {
+    pub fn apply(&self, machine: &mut Machine) {
+        if self.pressed {
+            machine.key_press(self.key);
+        } else {
+            machine.key_release(self.key);
+        }
+    }
+}
+
+impl fmt::Display for KeyEvent {
+    /// Formats the event as a key script line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = if self.pressed { "press" } else { "release" };
+        write!(f, "{} {} {:X}", self.frame, action, self.key)
+    }
+}
+
+/// Parses one `<frame> press|release <key>` line with the key in hex.
+pub(crate) fn parse_key_event(line: &str) -> Result<KeyEvent, &'static str> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 3 {
+        return Err("expected <frame> press|release <key>");
+    }
+
+    let frame = fields[0]
+        .parse::<u64>()
+        .map_err(|_| "invalid frame number")?;
+    let pressed = match fields[1] {
+        "press" => true,
+        "release" => false,
+        _ => return Err("expected press or release"),
+    };
+    let key = u8::from_str_radix(fields[2], 16)
+        .ok()
+        .filter(|key| *key <= 0xf)
+        .ok_or("key must be a hex digit from 0 to F")?;
+
+    Ok(KeyEvent {
+        frame,
+        key,
+        pressed,
+    })
+}
+
+/// Parses a key script, one `<frame> press|release <key>` event per line with the key in hex.
+///
+/// Blank lines and everything after `#` are ignored. Events are returned sorted by frame.
+pub fn parse_key_script(script: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut events = Vec::new();
+
+    for (index, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let event =
+            parse_key_event(line).map_err(|message| format!("Line {}: {}", index + 1, message))?;
+        events.push(event);
+    }
+
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_script_test() {
+        let script = "
+            # jump
+            12 release a
+            10 press A   # hold for two frames
+        ";
+
+        assert_eq!(
+            parse_key_script(script),
+            Ok(vec![
+                KeyEvent {
+                    frame: 10,
+                    key: 0xa,
+                    pressed: true
+                },
+                KeyEvent {
+                    frame: 12,
+                    key: 0xa,
+                    pressed: false
+                },
+            ])
+        );
+
+        assert_eq!(
+            parse_key_script("1 press 10"),
+            Err("Line 1: key must be a hex digit from 0 to F".to_string())
+        );
+        assert!(parse_key_script("1 tap 1").is_err());
+
+        let event = KeyEvent {
+            frame: 3,
+            key: 0xb,
+            pressed: false,
+        };
+        assert_eq!(parse_key_script(&event.to_string()), Ok(vec![event]));
+    }
+}
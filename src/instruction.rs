@@ -1,4 +1,6 @@
-use std::convert::TryInto;
+use std::{convert::TryInto, fmt, str::FromStr};
+
+use crate::quirks::Quirks;
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Instruction {
@@ -155,6 +157,60 @@ pub enum Instruction {
 
     // FX65
     LoadRegisters(u8),
+
+    // 00CN
+    ScrollDown(u8),
+
+    // 00FB
+    ScrollRight,
+
+    // 00FC
+    ScrollLeft,
+
+    // 00FD
+    Exit,
+
+    // 00FE
+    LowResolution,
+
+    // 00FF
+    HighResolution,
+
+    // FX30
+    SetIToBigFontLocation(u8),
+
+    // FX75
+    SaveFlags(u8),
+
+    // FX85
+    LoadFlags(u8),
+
+    // 00DN
+    ScrollUp(u8),
+
+    // 5XY2
+    SaveRegisterRange {
+        register_x: u8,
+        register_y: u8,
+    },
+
+    // 5XY3
+    LoadRegisterRange {
+        register_x: u8,
+        register_y: u8,
+    },
+
+    // F000 NNNN, the address is stored in the word following the opcode
+    StoreNextWordToI,
+
+    // FN01
+    SelectPlanes(u8),
+
+    // F002
+    LoadAudioPattern,
+
+    // FX3A
+    SetPitchFromRegister(u8),
 }
 
 fn split_opcode(instruction: u16) -> (u8, u8, u8, u8) {
@@ -163,12 +219,12 @@ fn split_opcode(instruction: u16) -> (u8, u8, u8, u8) {
     let code3 = (instruction & 0x0f00) >> 8;
     let code4 = (instruction & 0xf000) >> 12;
 
-    return (
+    (
         code4.try_into().unwrap(),
         code3.try_into().unwrap(),
         code2.try_into().unwrap(),
         code1.try_into().unwrap(),
-    );
+    )
 }
 
 fn combine_nibble2(a: u8, b: u8) -> u8 {
@@ -183,6 +239,13 @@ pub fn parse_opcode(instruction: u16) -> Option<Instruction> {
     match split_opcode(instruction) {
         (0x0, 0x0, 0xe, 0x0) => Some(Instruction::ClearScreen),
         (0x0, 0x0, 0xe, 0xe) => Some(Instruction::ReturnFromSubroutine),
+        (0x0, 0x0, 0xc, rows) => Some(Instruction::ScrollDown(rows)),
+        (0x0, 0x0, 0xd, rows) => Some(Instruction::ScrollUp(rows)),
+        (0x0, 0x0, 0xf, 0xb) => Some(Instruction::ScrollRight),
+        (0x0, 0x0, 0xf, 0xc) => Some(Instruction::ScrollLeft),
+        (0x0, 0x0, 0xf, 0xd) => Some(Instruction::Exit),
+        (0x0, 0x0, 0xf, 0xe) => Some(Instruction::LowResolution),
+        (0x0, 0x0, 0xf, 0xf) => Some(Instruction::HighResolution),
         (0x2, a, b, c) => Some(Instruction::CallSubroutineAtAddress(combine_nibble3(
             a, b, c,
         ))),
@@ -213,6 +276,14 @@ pub fn parse_opcode(instruction: u16) -> Option<Instruction> {
             register_x,
             register_y,
         }),
+        (0x5, register_x, register_y, 2) => Some(Instruction::SaveRegisterRange {
+            register_x,
+            register_y,
+        }),
+        (0x5, register_x, register_y, 3) => Some(Instruction::LoadRegisterRange {
+            register_x,
+            register_y,
+        }),
         (0x8, register_x, register_y, 0) => Some(Instruction::StoreYToX {
             register_x,
             register_y,
@@ -258,8 +329,12 @@ pub fn parse_opcode(instruction: u16) -> Option<Instruction> {
             register,
             mask: combine_nibble2(a, b),
         }),
-        (0xe, register, 0x9, 0xe) => Some(Instruction::SkipIfPressedKeyContainsRegisterValue(register)),
-        (0xe, register, 0xa, 0x1) => Some(Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(register)),
+        (0xe, register, 0x9, 0xe) => {
+            Some(Instruction::SkipIfPressedKeyContainsRegisterValue(register))
+        }
+        (0xe, register, 0xa, 0x1) => Some(
+            Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(register),
+        ),
         (0xf, register, 0x0, 0xa) => Some(Instruction::HaltAndGetKey(register)),
         (0xf, register, 0x0, 0x7) => Some(Instruction::SetRegisterFromDelayTimer(register)),
         (0xf, register, 0x1, 0x5) => Some(Instruction::SetDelayTimerFromRegister(register)),
@@ -269,10 +344,510 @@ pub fn parse_opcode(instruction: u16) -> Option<Instruction> {
         (0xf, register, 0x3, 0x3) => Some(Instruction::BinaryRepresentationFromRegister(register)),
         (0xf, register, 0x5, 0x5) => Some(Instruction::SaveRegisters(register)),
         (0xf, register, 0x6, 0x5) => Some(Instruction::LoadRegisters(register)),
+        (0xf, register, 0x3, 0x0) => Some(Instruction::SetIToBigFontLocation(register)),
+        (0xf, register, 0x7, 0x5) => Some(Instruction::SaveFlags(register)),
+        (0xf, register, 0x8, 0x5) => Some(Instruction::LoadFlags(register)),
+        (0xf, 0x0, 0x0, 0x0) => Some(Instruction::StoreNextWordToI),
+        (0xf, planes, 0x0, 0x1) => Some(Instruction::SelectPlanes(planes)),
+        (0xf, 0x0, 0x0, 0x2) => Some(Instruction::LoadAudioPattern),
+        (0xf, register, 0x3, 0xa) => Some(Instruction::SetPitchFromRegister(register)),
         _ => None,
     }
 }
 
+fn opcode_xy(prefix: u16, register_x: u8, register_y: u8, suffix: u16) -> u16 {
+    prefix << 12 | (register_x as u16) << 8 | (register_y as u16) << 4 | suffix
+}
+
+fn opcode_xnn(prefix: u16, register: u8, value: u8) -> u16 {
+    prefix << 12 | (register as u16) << 8 | value as u16
+}
+
+impl Instruction {
+    /// Opcode of the instruction, the inverse of `parse_opcode`.
+    ///
+    /// `StoreNextWordToI` only encodes the F000 word, the address follows it.
+    pub fn encode(&self) -> u16 {
+        match *self {
+            Instruction::ClearScreen => 0x00e0,
+            Instruction::ReturnFromSubroutine => 0x00ee,
+            Instruction::JumpToAddress(address) => 0x1000 | address,
+            Instruction::CallSubroutineAtAddress(address) => 0x2000 | address,
+            Instruction::SkipIfEqual { register, value } => opcode_xnn(0x3, register, value),
+            Instruction::SkipIfNotEqual { register, value } => opcode_xnn(0x4, register, value),
+            Instruction::SkipIfRegistersEqual {
+                register_x,
+                register_y,
+            } => opcode_xy(0x5, register_x, register_y, 0x0),
+            Instruction::SetV { register, value } => opcode_xnn(0x6, register, value),
+            Instruction::AddToRegister { register, value } => opcode_xnn(0x7, register, value),
+            Instruction::StoreYToX {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0x0),
+            Instruction::OrRegisters {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0x1),
+            Instruction::AndRegisters {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0x2),
+            Instruction::XorRegisters {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0x3),
+            Instruction::AddRegisters {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0x4),
+            Instruction::SubtractXMinusY {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0x5),
+            Instruction::ShiftRegisterRight {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0x6),
+            Instruction::SubtractYMinusX {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0x7),
+            Instruction::ShiftRegisterLeft {
+                register_x,
+                register_y,
+            } => opcode_xy(0x8, register_x, register_y, 0xe),
+            Instruction::SkipIfRegistersNotEqual {
+                register_x,
+                register_y,
+            } => opcode_xy(0x9, register_x, register_y, 0x0),
+            Instruction::StoreAddrToI(address) => 0xa000 | address,
+            Instruction::JumpWithOffset(address) => 0xb000 | address,
+            Instruction::SetRandomNumber { register, mask } => opcode_xnn(0xc, register, mask),
+            Instruction::Draw {
+                register_x,
+                register_y,
+                bytes,
+            } => opcode_xy(0xd, register_x, register_y, bytes as u16),
+            Instruction::SkipIfPressedKeyContainsRegisterValue(register) => {
+                opcode_xnn(0xe, register, 0x9e)
+            }
+            Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(register) => {
+                opcode_xnn(0xe, register, 0xa1)
+            }
+            Instruction::SetRegisterFromDelayTimer(register) => opcode_xnn(0xf, register, 0x07),
+            Instruction::HaltAndGetKey(register) => opcode_xnn(0xf, register, 0x0a),
+            Instruction::SetDelayTimerFromRegister(register) => opcode_xnn(0xf, register, 0x15),
+            Instruction::SetSoundTimerFromRegister(register) => opcode_xnn(0xf, register, 0x18),
+            Instruction::AddRegisterToI(register) => opcode_xnn(0xf, register, 0x1e),
+            Instruction::SetIToFontLocation(register) => opcode_xnn(0xf, register, 0x29),
+            Instruction::BinaryRepresentationFromRegister(register) => {
+                opcode_xnn(0xf, register, 0x33)
+            }
+            Instruction::SaveRegisters(register) => opcode_xnn(0xf, register, 0x55),
+            Instruction::LoadRegisters(register) => opcode_xnn(0xf, register, 0x65),
+            Instruction::ScrollDown(rows) => 0x00c0 | rows as u16,
+            Instruction::ScrollRight => 0x00fb,
+            Instruction::ScrollLeft => 0x00fc,
+            Instruction::Exit => 0x00fd,
+            Instruction::LowResolution => 0x00fe,
+            Instruction::HighResolution => 0x00ff,
+            Instruction::SetIToBigFontLocation(register) => opcode_xnn(0xf, register, 0x30),
+            Instruction::SaveFlags(register) => opcode_xnn(0xf, register, 0x75),
+            Instruction::LoadFlags(register) => opcode_xnn(0xf, register, 0x85),
+            Instruction::ScrollUp(rows) => 0x00d0 | rows as u16,
+            Instruction::SaveRegisterRange {
+                register_x,
+                register_y,
+            } => opcode_xy(0x5, register_x, register_y, 0x2),
+            Instruction::LoadRegisterRange {
+                register_x,
+                register_y,
+            } => opcode_xy(0x5, register_x, register_y, 0x3),
+            Instruction::StoreNextWordToI => 0xf000,
+            Instruction::SelectPlanes(planes) => opcode_xnn(0xf, planes, 0x01),
+            Instruction::LoadAudioPattern => 0xf002,
+            Instruction::SetPitchFromRegister(register) => opcode_xnn(0xf, register, 0x3a),
+        }
+    }
+
+    /// Mnemonic as printed by `to_string_with`, with `address` naming the target of jumps,
+    /// calls and I loads, for example as a label.
+    pub fn mnemonic(&self, quirks: &Quirks, address: impl Fn(u16) -> String) -> String {
+        match *self {
+            Instruction::ClearScreen => "CLS".to_string(),
+            Instruction::ReturnFromSubroutine => "RET".to_string(),
+            Instruction::JumpToAddress(target) => format!("JP {}", address(target)),
+            Instruction::CallSubroutineAtAddress(target) => format!("CALL {}", address(target)),
+            Instruction::SkipIfEqual { register, value } => {
+                format!("SE V{:X}, 0x{:02X}", register, value)
+            }
+            Instruction::SkipIfNotEqual { register, value } => {
+                format!("SNE V{:X}, 0x{:02X}", register, value)
+            }
+            Instruction::SkipIfRegistersEqual {
+                register_x,
+                register_y,
+            } => format!("SE V{:X}, V{:X}", register_x, register_y),
+            Instruction::SetV { register, value } => {
+                format!("LD V{:X}, 0x{:02X}", register, value)
+            }
+            Instruction::AddToRegister { register, value } => {
+                format!("ADD V{:X}, 0x{:02X}", register, value)
+            }
+            Instruction::StoreYToX {
+                register_x,
+                register_y,
+            } => format!("LD V{:X}, V{:X}", register_x, register_y),
+            Instruction::OrRegisters {
+                register_x,
+                register_y,
+            } => format!("OR V{:X}, V{:X}", register_x, register_y),
+            Instruction::AndRegisters {
+                register_x,
+                register_y,
+            } => format!("AND V{:X}, V{:X}", register_x, register_y),
+            Instruction::XorRegisters {
+                register_x,
+                register_y,
+            } => format!("XOR V{:X}, V{:X}", register_x, register_y),
+            Instruction::AddRegisters {
+                register_x,
+                register_y,
+            } => format!("ADD V{:X}, V{:X}", register_x, register_y),
+            Instruction::SubtractXMinusY {
+                register_x,
+                register_y,
+            } => format!("SUB V{:X}, V{:X}", register_x, register_y),
+            Instruction::ShiftRegisterRight {
+                register_x,
+                register_y,
+            } => format!("SHR V{:X}, V{:X}", register_x, register_y),
+            Instruction::SubtractYMinusX {
+                register_x,
+                register_y,
+            } => format!("SUBN V{:X}, V{:X}", register_x, register_y),
+            Instruction::ShiftRegisterLeft {
+                register_x,
+                register_y,
+            } => format!("SHL V{:X}, V{:X}", register_x, register_y),
+            Instruction::SkipIfRegistersNotEqual {
+                register_x,
+                register_y,
+            } => format!("SNE V{:X}, V{:X}", register_x, register_y),
+            Instruction::StoreAddrToI(target) => format!("LD I, {}", address(target)),
+            Instruction::JumpWithOffset(target) => {
+                // with the quirk, BXNN adds the register named by its own first digit
+                let register = if quirks.jump_with_offset_uses_vx {
+                    target >> 8
+                } else {
+                    0
+                };
+                format!("JP V{:X}, {}", register, address(target))
+            }
+            Instruction::SetRandomNumber { register, mask } => {
+                format!("RND V{:X}, 0x{:02X}", register, mask)
+            }
+            Instruction::Draw {
+                register_x,
+                register_y,
+                bytes,
+            } => format!("DRW V{:X}, V{:X}, {}", register_x, register_y, bytes),
+            Instruction::SkipIfPressedKeyContainsRegisterValue(register) => {
+                format!("SKP V{:X}", register)
+            }
+            Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(register) => {
+                format!("SKNP V{:X}", register)
+            }
+            Instruction::SetRegisterFromDelayTimer(register) => format!("LD V{:X}, DT", register),
+            Instruction::HaltAndGetKey(register) => format!("LD V{:X}, K", register),
+            Instruction::SetDelayTimerFromRegister(register) => format!("LD DT, V{:X}", register),
+            Instruction::SetSoundTimerFromRegister(register) => format!("LD ST, V{:X}", register),
+            Instruction::AddRegisterToI(register) => format!("ADD I, V{:X}", register),
+            Instruction::SetIToFontLocation(register) => format!("LD F, V{:X}", register),
+            Instruction::BinaryRepresentationFromRegister(register) => {
+                format!("LD B, V{:X}", register)
+            }
+            Instruction::SaveRegisters(register) => format!("LD [I], V{:X}", register),
+            Instruction::LoadRegisters(register) => format!("LD V{:X}, [I]", register),
+            Instruction::ScrollDown(rows) => format!("SCD {}", rows),
+            Instruction::ScrollRight => "SCR".to_string(),
+            Instruction::ScrollLeft => "SCL".to_string(),
+            Instruction::Exit => "EXIT".to_string(),
+            Instruction::LowResolution => "LOW".to_string(),
+            Instruction::HighResolution => "HIGH".to_string(),
+            Instruction::SetIToBigFontLocation(register) => format!("LD HF, V{:X}", register),
+            Instruction::SaveFlags(register) => format!("LD R, V{:X}", register),
+            Instruction::LoadFlags(register) => format!("LD V{:X}, R", register),
+            Instruction::ScrollUp(rows) => format!("SCU {}", rows),
+            Instruction::SaveRegisterRange {
+                register_x,
+                register_y,
+            } => format!("SAVE V{:X}, V{:X}", register_x, register_y),
+            Instruction::LoadRegisterRange {
+                register_x,
+                register_y,
+            } => format!("LOAD V{:X}, V{:X}", register_x, register_y),
+            Instruction::StoreNextWordToI => "LD I, LONG".to_string(),
+            Instruction::SelectPlanes(planes) => format!("PLANE {}", planes),
+            Instruction::LoadAudioPattern => "AUDIO".to_string(),
+            Instruction::SetPitchFromRegister(register) => format!("PITCH V{:X}", register),
+        }
+    }
+}
+
+impl Instruction {
+    /// Mnemonic of the instruction as run with `quirks`, which decide the register BNNN adds.
+    pub fn to_string_with(&self, quirks: &Quirks) -> String {
+        self.mnemonic(quirks, |address| format!("0x{:03X}", address))
+    }
+}
+
+/// Cowgod style mnemonics, such as `LD V3, 0x0C` and `DRW V0, V1, 15`, as run with the
+/// default quirks.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(&Quirks::default()))
+    }
+}
+
+/// Name of an `Instruction` variant, such as `Draw` for `Draw { .. }`.
+pub fn instruction_name(instruction: &Instruction) -> String {
+    format!("{:?}", instruction)
+        .chars()
+        .take_while(|character| character.is_alphanumeric())
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Operand {
+    Register(u8),
+    Number(u16),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long,
+}
+
+fn parse_operand(operand: &str) -> Option<Operand> {
+    let operand = operand.to_ascii_uppercase();
+    let parsed = match operand.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "HF" => Operand::BigFont,
+        "B" => Operand::Bcd,
+        "R" => Operand::Flags,
+        "LONG" => Operand::Long,
+        _ => {
+            if let Some(hex) = operand.strip_prefix("0X") {
+                Operand::Number(u16::from_str_radix(hex, 16).ok()?)
+            } else if let Some(register) = operand.strip_prefix('V') {
+                let register = u8::from_str_radix(register, 16).ok()?;
+                if register > 0xf {
+                    return None;
+                }
+                Operand::Register(register)
+            } else {
+                Operand::Number(operand.parse().ok()?)
+            }
+        }
+    };
+    Some(parsed)
+}
+
+/// Parses the mnemonics printed by `Display`, case insensitively and with numbers in
+/// decimal or `0x` hex.
+impl FromStr for Instruction {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Instruction, String> {
+        let invalid = || format!("Invalid instruction {}", text.trim());
+
+        let text = text.trim();
+        let (name, operands) = match text.split_once(char::is_whitespace) {
+            Some((name, operands)) => (name, operands.split(',').collect()),
+            None => (text, Vec::new()),
+        };
+        let operands = operands
+            .iter()
+            .map(|operand| parse_operand(operand.trim()))
+            .collect::<Option<Vec<Operand>>>()
+            .ok_or_else(invalid)?;
+
+        use Operand::*;
+        let instruction = match (name.to_ascii_uppercase().as_str(), operands.as_slice()) {
+            ("CLS", []) => Instruction::ClearScreen,
+            ("RET", []) => Instruction::ReturnFromSubroutine,
+            ("JP", [Number(address)]) if *address <= 0xfff => Instruction::JumpToAddress(*address),
+            ("JP", [Register(register), Number(address)])
+                if *address <= 0xfff && (*register == 0 || *register as u16 == *address >> 8) =>
+            {
+                Instruction::JumpWithOffset(*address)
+            }
+            ("CALL", [Number(address)]) if *address <= 0xfff => {
+                Instruction::CallSubroutineAtAddress(*address)
+            }
+            ("SE", [Register(register), Number(value)]) if *value <= 0xff => {
+                Instruction::SkipIfEqual {
+                    register: *register,
+                    value: *value as u8,
+                }
+            }
+            ("SNE", [Register(register), Number(value)]) if *value <= 0xff => {
+                Instruction::SkipIfNotEqual {
+                    register: *register,
+                    value: *value as u8,
+                }
+            }
+            ("SE", [Register(register_x), Register(register_y)]) => {
+                Instruction::SkipIfRegistersEqual {
+                    register_x: *register_x,
+                    register_y: *register_y,
+                }
+            }
+            ("SNE", [Register(register_x), Register(register_y)]) => {
+                Instruction::SkipIfRegistersNotEqual {
+                    register_x: *register_x,
+                    register_y: *register_y,
+                }
+            }
+            ("LD", [Register(register), Number(value)]) if *value <= 0xff => Instruction::SetV {
+                register: *register,
+                value: *value as u8,
+            },
+            ("ADD", [Register(register), Number(value)]) if *value <= 0xff => {
+                Instruction::AddToRegister {
+                    register: *register,
+                    value: *value as u8,
+                }
+            }
+            ("RND", [Register(register), Number(mask)]) if *mask <= 0xff => {
+                Instruction::SetRandomNumber {
+                    register: *register,
+                    mask: *mask as u8,
+                }
+            }
+            (
+                operation @ ("LD" | "OR" | "AND" | "XOR" | "ADD" | "SUB" | "SHR" | "SUBN" | "SHL"
+                | "SAVE" | "LOAD"),
+                [Register(register_x), Register(register_y)],
+            ) => {
+                let (register_x, register_y) = (*register_x, *register_y);
+                match operation {
+                    "LD" => Instruction::StoreYToX {
+                        register_x,
+                        register_y,
+                    },
+                    "OR" => Instruction::OrRegisters {
+                        register_x,
+                        register_y,
+                    },
+                    "AND" => Instruction::AndRegisters {
+                        register_x,
+                        register_y,
+                    },
+                    "XOR" => Instruction::XorRegisters {
+                        register_x,
+                        register_y,
+                    },
+                    "ADD" => Instruction::AddRegisters {
+                        register_x,
+                        register_y,
+                    },
+                    "SUB" => Instruction::SubtractXMinusY {
+                        register_x,
+                        register_y,
+                    },
+                    "SHR" => Instruction::ShiftRegisterRight {
+                        register_x,
+                        register_y,
+                    },
+                    "SUBN" => Instruction::SubtractYMinusX {
+                        register_x,
+                        register_y,
+                    },
+                    "SHL" => Instruction::ShiftRegisterLeft {
+                        register_x,
+                        register_y,
+                    },
+                    "SAVE" => Instruction::SaveRegisterRange {
+                        register_x,
+                        register_y,
+                    },
+                    _ => Instruction::LoadRegisterRange {
+                        register_x,
+                        register_y,
+                    },
+                }
+            }
+            ("LD", [I, Number(address)]) if *address <= 0xfff => {
+                Instruction::StoreAddrToI(*address)
+            }
+            ("LD", [I, Long]) => Instruction::StoreNextWordToI,
+            ("DRW", [Register(register_x), Register(register_y), Number(bytes)])
+                if *bytes <= 0xf =>
+            {
+                Instruction::Draw {
+                    register_x: *register_x,
+                    register_y: *register_y,
+                    bytes: *bytes as u8,
+                }
+            }
+            ("SKP", [Register(register)]) => {
+                Instruction::SkipIfPressedKeyContainsRegisterValue(*register)
+            }
+            ("SKNP", [Register(register)]) => {
+                Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(*register)
+            }
+            ("LD", [Register(register), DelayTimer]) => {
+                Instruction::SetRegisterFromDelayTimer(*register)
+            }
+            ("LD", [Register(register), Key]) => Instruction::HaltAndGetKey(*register),
+            ("LD", [DelayTimer, Register(register)]) => {
+                Instruction::SetDelayTimerFromRegister(*register)
+            }
+            ("LD", [SoundTimer, Register(register)]) => {
+                Instruction::SetSoundTimerFromRegister(*register)
+            }
+            ("ADD", [I, Register(register)]) => Instruction::AddRegisterToI(*register),
+            ("LD", [Font, Register(register)]) => Instruction::SetIToFontLocation(*register),
+            ("LD", [BigFont, Register(register)]) => Instruction::SetIToBigFontLocation(*register),
+            ("LD", [Bcd, Register(register)]) => {
+                Instruction::BinaryRepresentationFromRegister(*register)
+            }
+            ("LD", [IndirectI, Register(register)]) => Instruction::SaveRegisters(*register),
+            ("LD", [Register(register), IndirectI]) => Instruction::LoadRegisters(*register),
+            ("LD", [Flags, Register(register)]) => Instruction::SaveFlags(*register),
+            ("LD", [Register(register), Flags]) => Instruction::LoadFlags(*register),
+            ("SCD", [Number(rows)]) if *rows <= 0xf => Instruction::ScrollDown(*rows as u8),
+            ("SCU", [Number(rows)]) if *rows <= 0xf => Instruction::ScrollUp(*rows as u8),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LowResolution,
+            ("HIGH", []) => Instruction::HighResolution,
+            ("PLANE", [Number(planes)]) if *planes <= 0xf => {
+                Instruction::SelectPlanes(*planes as u8)
+            }
+            ("AUDIO", []) => Instruction::LoadAudioPattern,
+            ("PITCH", [Register(register)]) => Instruction::SetPitchFromRegister(*register),
+            _ => return Err(invalid()),
+        };
+
+        Ok(instruction)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -412,9 +987,51 @@ mod tests {
             (0xfe07, Instruction::SetRegisterFromDelayTimer(0xe)),
             (0xfe18, Instruction::SetSoundTimerFromRegister(0xe)),
             (0xf333, Instruction::BinaryRepresentationFromRegister(0x3)),
-            (0xe29e, Instruction::SkipIfPressedKeyContainsRegisterValue(0x2)),
-            (0xe8a1, Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(0x8)),
+            (
+                0xe29e,
+                Instruction::SkipIfPressedKeyContainsRegisterValue(0x2),
+            ),
+            (
+                0xe8a1,
+                Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(0x8),
+            ),
             (0xbabc, Instruction::JumpWithOffset(0xabc)),
+            (0x00c4, Instruction::ScrollDown(0x4)),
+            (0x00fb, Instruction::ScrollRight),
+            (0x00fc, Instruction::ScrollLeft),
+            (0x00fd, Instruction::Exit),
+            (0x00fe, Instruction::LowResolution),
+            (0x00ff, Instruction::HighResolution),
+            (
+                0xd120,
+                Instruction::Draw {
+                    register_x: 1,
+                    register_y: 2,
+                    bytes: 0,
+                },
+            ),
+            (0xf430, Instruction::SetIToBigFontLocation(0x4)),
+            (0xf775, Instruction::SaveFlags(0x7)),
+            (0xf385, Instruction::LoadFlags(0x3)),
+            (0x00d3, Instruction::ScrollUp(0x3)),
+            (
+                0x5122,
+                Instruction::SaveRegisterRange {
+                    register_x: 0x1,
+                    register_y: 0x2,
+                },
+            ),
+            (
+                0x5a33,
+                Instruction::LoadRegisterRange {
+                    register_x: 0xa,
+                    register_y: 0x3,
+                },
+            ),
+            (0xf000, Instruction::StoreNextWordToI),
+            (0xf201, Instruction::SelectPlanes(0x2)),
+            (0xf002, Instruction::LoadAudioPattern),
+            (0xf43a, Instruction::SetPitchFromRegister(0x4)),
         ];
 
         for (instruction, opcode) in instructions_and_opcodes {
@@ -427,4 +1044,55 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn encode_test() {
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = parse_opcode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn mnemonic_test() {
+        assert_eq!(
+            Instruction::SetV {
+                register: 3,
+                value: 0x0c
+            }
+            .to_string(),
+            "LD V3, 0x0C"
+        );
+        assert_eq!(parse_opcode(0xd01f).unwrap().to_string(), "DRW V0, V1, 15");
+        assert_eq!(parse_opcode(0x1228).unwrap().to_string(), "JP 0x228");
+        assert_eq!("ld  i, 0x22A".parse(), Ok(Instruction::StoreAddrToI(0x22a)));
+        assert_eq!("drw v0,v1,15".parse(), Ok(parse_opcode(0xd01f).unwrap()));
+
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = parse_opcode(opcode) {
+                assert_eq!(instruction.to_string().parse(), Ok(instruction));
+            }
+        }
+
+        assert!("JP 0x1000".parse::<Instruction>().is_err());
+        assert!("JP V3, 0x228".parse::<Instruction>().is_err());
+    }
+
+    #[test]
+    fn jump_with_offset_mnemonic_test() {
+        let jump = parse_opcode(0xb345).unwrap();
+
+        assert_eq!(jump.to_string(), "JP V0, 0x345");
+        assert_eq!(jump.to_string_with(&Quirks::super_chip()), "JP V3, 0x345");
+        assert_eq!("JP V3, 0x345".parse(), Ok(jump));
+        assert_eq!(
+            parse_opcode(0xb045)
+                .unwrap()
+                .to_string_with(&Quirks::super_chip()),
+            "JP V0, 0x045"
+        );
+        assert!("LD V3".parse::<Instruction>().is_err());
+        assert!("NOP".parse::<Instruction>().is_err());
+    }
 }
@@ -0,0 +1,209 @@
+//! Which keyboard keys press which of the 16 CHIP-8 keys.
+//!
+//! Keys are named after their physical position on a US keyboard, using the SDL scancode
+//! names such as `Q`, `1`, `Up` or `Keypad 7`, so a layout stays in the same place on
+//! AZERTY, Dvorak and other keyboards.
+
+use std::str::FromStr;
+
+pub struct Preset {
+    pub name: &'static str,
+    pub bindings: &'static [(&'static str, u8)],
+}
+
+/// Built-in layouts, the first one being the default.
+pub const PRESETS: &[Preset] = &[
+    // the left hand block of four rows, matching the COSMAC VIP keypad
+    //   1 2 3 C     1 2 3 4
+    //   4 5 6 D  <- Q W E R
+    //   7 8 9 E     A S D F
+    //   A 0 B F     Z X C V
+    Preset {
+        name: "classic",
+        bindings: &[
+            ("1", 0x1),
+            ("2", 0x2),
+            ("3", 0x3),
+            ("4", 0xc),
+            ("Q", 0x4),
+            ("W", 0x5),
+            ("E", 0x6),
+            ("R", 0xd),
+            ("A", 0x7),
+            ("S", 0x8),
+            ("D", 0x9),
+            ("F", 0xe),
+            ("Z", 0xa),
+            ("X", 0x0),
+            ("C", 0xb),
+            ("V", 0xf),
+        ],
+    },
+    // digits where the numeric keypad has them, A-F on the keys around them
+    Preset {
+        name: "numpad",
+        bindings: &[
+            ("Keypad 0", 0x0),
+            ("Keypad 1", 0x1),
+            ("Keypad 2", 0x2),
+            ("Keypad 3", 0x3),
+            ("Keypad 4", 0x4),
+            ("Keypad 5", 0x5),
+            ("Keypad 6", 0x6),
+            ("Keypad 7", 0x7),
+            ("Keypad 8", 0x8),
+            ("Keypad 9", 0x9),
+            ("Keypad /", 0xa),
+            ("Keypad *", 0xb),
+            ("Keypad -", 0xc),
+            ("Keypad +", 0xd),
+            ("Keypad Enter", 0xe),
+            ("Keypad .", 0xf),
+        ],
+    },
+    // the layout of earlier versions, with the arrow keys on 2, 4, 6 and 8
+    Preset {
+        name: "legacy",
+        bindings: &[
+            ("Up", 0x2),
+            ("Left", 0x4),
+            ("Right", 0x6),
+            ("Down", 0x8),
+            ("1", 0x1),
+            ("2", 0x2),
+            ("3", 0x3),
+            ("Q", 0x4),
+            ("W", 0x5),
+            ("E", 0x6),
+            ("A", 0x7),
+            ("S", 0x8),
+            ("D", 0x9),
+            ("X", 0x0),
+            ("Z", 0xa),
+            ("C", 0xb),
+            ("4", 0xc),
+            ("R", 0xd),
+            ("F", 0xe),
+            ("V", 0xf),
+        ],
+    },
+];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct KeyMap {
+    bindings: Vec<(String, u8)>,
+}
+
+impl KeyMap {
+    /// Layout of the preset named `name`.
+    pub fn preset(name: &str) -> Result<KeyMap, String> {
+        match PRESETS.iter().find(|preset| preset.name == name) {
+            Some(preset) => Ok(KeyMap {
+                bindings: preset
+                    .bindings
+                    .iter()
+                    .map(|(key, chip8_key)| (key.to_string(), *chip8_key))
+                    .collect(),
+            }),
+            None => {
+                let names: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+                Err(format!(
+                    "Unknown key map {}, expected one of {}",
+                    name,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+
+    /// Makes `key` press `chip8_key`, replacing what it pressed before. Key names are
+    /// compared ignoring case, like SDL does.
+    pub fn bind(&mut self, key: &str, chip8_key: u8) {
+        self.bindings
+            .retain(|(bound, _)| !bound.eq_ignore_ascii_case(key));
+        self.bindings.push((key.to_string(), chip8_key));
+    }
+
+    /// Applies comma separated `key=chip8-key` bindings, such as `Up=2, Down=8`, where the
+    /// CHIP-8 key is a hex digit.
+    pub fn bind_all(&mut self, bindings: &str) -> Result<(), String> {
+        for binding in bindings.split(',') {
+            // split at the last `=` so the `=` key itself can be bound
+            let (key, chip8_key) = binding
+                .rsplit_once('=')
+                .map(|(key, chip8_key)| (key.trim(), chip8_key.trim()))
+                .filter(|(key, _)| !key.is_empty())
+                .ok_or(format!("Invalid key binding {}, expected key=hex", binding))?;
+
+            let chip8_key = u8::from_str_radix(chip8_key, 16)
+                .ok()
+                .filter(|chip8_key| *chip8_key <= 0xf)
+                .ok_or(format!("Invalid CHIP-8 key {}, expected 0-F", chip8_key))?;
+            self.bind(key, chip8_key);
+        }
+
+        Ok(())
+    }
+
+    /// Key names and the CHIP-8 keys they press, in the order they were bound.
+    pub fn bindings(&self) -> &[(String, u8)] {
+        &self.bindings
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        KeyMap::preset(PRESETS[0].name).unwrap()
+    }
+}
+
+impl FromStr for KeyMap {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<KeyMap, String> {
+        KeyMap::preset(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip8_key(keymap: &KeyMap, key: &str) -> Option<u8> {
+        keymap
+            .bindings()
+            .iter()
+            .find(|(bound, _)| bound == key)
+            .map(|(_, chip8_key)| *chip8_key)
+    }
+
+    #[test]
+    fn presets_test() {
+        for preset in PRESETS {
+            let mut chip8_keys: Vec<u8> = preset.bindings.iter().map(|(_, key)| *key).collect();
+            chip8_keys.sort_unstable();
+            chip8_keys.dedup();
+            assert_eq!(chip8_keys, (0..16).collect::<Vec<u8>>(), "{}", preset.name);
+        }
+
+        let classic = KeyMap::default();
+        assert_eq!(chip8_key(&classic, "X"), Some(0x0));
+        assert_eq!(chip8_key(&classic, "V"), Some(0xf));
+        assert!("dvorak".parse::<KeyMap>().is_err());
+    }
+
+    #[test]
+    fn bind_all_test() {
+        let mut keymap: KeyMap = "numpad".parse().unwrap();
+        keymap.bind_all("Up=2, keypad 0=a,==F").unwrap();
+
+        assert_eq!(chip8_key(&keymap, "Up"), Some(0x2));
+        assert_eq!(chip8_key(&keymap, "Keypad 0"), None);
+        assert_eq!(chip8_key(&keymap, "keypad 0"), Some(0xa));
+        assert_eq!(chip8_key(&keymap, "="), Some(0xf));
+        assert_eq!(keymap.bindings().len(), 18);
+
+        assert!(keymap.bind_all("Up=10").is_err());
+        assert!(keymap.bind_all("Up").is_err());
+    }
+}
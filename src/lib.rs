@@ -0,0 +1,28 @@
+//! CHIP-8 interpreter core.
+//!
+//! Everything in this crate is independent of SDL, so the interpreter can be
+//! embedded in tools, tests and bots. The SDL frontend lives in `main.rs` and is
+//! only built with the `frontend-sdl` feature.
+
+pub mod assembler;
+pub mod cli;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod error;
+pub mod flicker;
+pub mod gdb;
+pub mod input;
+pub mod instruction;
+pub mod keymap;
+pub mod movie;
+pub mod palette;
+pub mod program;
+pub mod quirks;
+pub mod random;
+pub mod recording;
+pub mod rewind;
+pub mod screenshot;
+pub mod state;
+pub mod trace;
+pub mod viewport;
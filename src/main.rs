@@ -1,76 +1,338 @@
-pub mod instruction;
-pub mod program;
-
-use std::env;
-use std::{convert::TryInto, time::Duration};
-
-use program::{Machine, PixelBuffer, NUM_COLS, NUM_ROWS};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::{env, fs, path::Path};
+
+use chip_8_emulator::cli::{
+    args_with_config, has_flag, keymap_from_args, option_value, palette_from_args, parse_option,
+    MachineOptions, CONFIG_USAGE, KEYMAP_OPTIONS_USAGE, MACHINE_OPTIONS_USAGE,
+    PALETTE_OPTIONS_USAGE,
+};
+use chip_8_emulator::display::{PixelBuffer, NUM_COLS, NUM_ROWS};
+use chip_8_emulator::flicker::FlickerFilter;
+use chip_8_emulator::keymap::KeyMap;
+use chip_8_emulator::movie::{Movie, Replay};
+use chip_8_emulator::palette::{Palette, THEMES};
+use chip_8_emulator::program::{Machine, AUDIO_PATTERN_BYTES};
+use chip_8_emulator::recording::{VideoFormat, VideoRecorder};
+use chip_8_emulator::rewind::RewindBuffer;
+use chip_8_emulator::screenshot::ImageFormat;
+use chip_8_emulator::viewport::{PixelAspect, Scaling, Viewport};
 use sdl2::{
     audio::{AudioCallback, AudioSpecDesired},
     event::Event,
-    keyboard::Keycode,
-    pixels::Color,
+    hint,
+    keyboard::{Keycode, Mod, Scancode},
+    pixels::{Color, PixelFormatEnum},
     rect::Rect,
-    render::WindowCanvas,
+    render::{Texture, TextureCreator, WindowCanvas},
+    video::{FullscreenType, WindowContext},
 };
 
-use common_macros::hash_map;
+/// Initial size of a low-res pixel in the window.
+const DEFAULT_WINDOW_SCALE: u32 = 10;
+const DEFAULT_REWIND_SECONDS: u32 = 10;
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
-const SCALE: u32 = 10;
+const PATTERN_BITS: f32 = (AUDIO_PATTERN_BYTES * 8) as f32;
 
-struct SquareWave {
+/// Plays the machine's audio pattern buffer one bit per sample step.
+struct PatternWave {
+    pattern: [u8; AUDIO_PATTERN_BYTES],
+    sample_rate: f32,
+    // pattern bits advanced per output sample
     phase_inc: f32,
     phase: f32,
     volume: f32,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for PatternWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            let bit = self.phase as usize;
+            let is_set = self.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+
+            *x = if is_set { self.volume } else { -self.volume };
+            self.phase = (self.phase + self.phase_inc) % PATTERN_BITS;
         }
     }
 }
 
-fn draw_pixel_buffer(canvas: &mut WindowCanvas, pixel_buffer: &PixelBuffer) -> Result<(), String> {
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
+fn color(rgb: [u8; 3]) -> Color {
+    Color::RGB(rgb[0], rgb[1], rgb[2])
+}
+
+/// Size of a buffer pixel at the initial window size, where hi-res pixels are half as big.
+fn pixel_size(pixel_buffer: &PixelBuffer, window_scale: u32) -> usize {
+    (NUM_COLS * window_scale as usize / pixel_buffer.width()).max(1)
+}
+
+/// Presents frames through a streaming texture the size of the pixel buffer, which the GPU
+/// scales to the window.
+struct Screen<'a> {
+    texture_creator: &'a TextureCreator<WindowContext>,
+    texture: Option<Texture<'a>>,
+    /// Frame and colours in the texture, to skip uploading unchanged frames.
+    uploaded: Option<(PixelBuffer, Vec<[u8; 3]>)>,
+}
+
+impl<'a> Screen<'a> {
+    fn new(texture_creator: &'a TextureCreator<WindowContext>) -> Screen<'a> {
+        Screen {
+            texture_creator,
+            texture: None,
+            uploaded: None,
+        }
+    }
+
+    /// Copies the buffer to the texture, unless it is already there.
+    fn upload(&mut self, pixel_buffer: &PixelBuffer, colors: &[[u8; 3]]) -> Result<(), String> {
+        if let Some((frame, frame_colors)) = &self.uploaded {
+            if frame == pixel_buffer && frame_colors.as_slice() == colors {
+                return Ok(());
+            }
+        }
 
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
-    for y in 0..NUM_ROWS {
-        for x in 0..NUM_COLS {
-            if pixel_buffer[y][x] {
-                let x = (x * 10)
-                    .try_into()
-                    .map_err(|value| format!("Failed converting {} to i32", value))?;
-                let y = (y * 10)
-                    .try_into()
-                    .map_err(|value| format!("Failed converting {} to i32", value))?;
-
-                canvas.fill_rect(Rect::new(x, y, 10, 10)).unwrap();
+        let (width, height) = (pixel_buffer.width(), pixel_buffer.height());
+        let resized = match &self.texture {
+            Some(texture) => {
+                let query = texture.query();
+                (query.width as usize, query.height as usize) != (width, height)
             }
+            None => true,
+        };
+        if resized {
+            let texture = self
+                .texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                .map_err(|error| error.to_string())?;
+            self.texture = Some(texture);
+        }
+
+        if let Some(texture) = &mut self.texture {
+            texture
+                .update(None, &pixel_buffer.to_rgb(colors, 1), width * 3)
+                .map_err(|error| error.to_string())?;
         }
+        self.uploaded = Some((pixel_buffer.clone(), colors.to_vec()));
+        Ok(())
     }
 
-    canvas.present();
+    /// Draws the buffer centred in the window, with black borders around it.
+    fn draw(
+        &mut self,
+        canvas: &mut WindowCanvas,
+        pixel_buffer: &PixelBuffer,
+        colors: &[[u8; 3]],
+        scaling: Scaling,
+        aspect: PixelAspect,
+    ) -> Result<(), String> {
+        self.upload(pixel_buffer, colors)?;
+
+        // in physical pixels, which differ from window coordinates on HiDPI displays
+        let viewport = Viewport::new(
+            canvas.output_size()?,
+            (pixel_buffer.width(), pixel_buffer.height()),
+            scaling,
+            aspect,
+        );
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        if let Some(texture) = &self.texture {
+            let destination = Rect::new(
+                viewport.x as i32,
+                viewport.y as i32,
+                viewport.width,
+                viewport.height,
+            );
+            canvas.copy(texture, None, destination)?;
+        }
+        canvas.present();
 
-    Ok(())
+        Ok(())
+    }
 }
 
-fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
+fn toggle_fullscreen(canvas: &mut WindowCanvas) -> Result<(), String> {
+    let window = canvas.window_mut();
+    let fullscreen = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
+    window.set_fullscreen(fullscreen)
+}
+
+/// Save slot bound to a function key, F1 to F9.
+fn save_slot(keycode: Keycode) -> Option<u8> {
+    let slots = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+        Keycode::F9,
+    ];
+
+    slots
+        .iter()
+        .position(|slot| *slot == keycode)
+        .map(|index| index as u8 + 1)
+}
 
-    let file_name = &args[1];
+/// Save states are kept next to the ROM, one file per slot.
+fn save_slot_path(rom_file_name: &str, slot: u8) -> String {
+    format!("{}.state{}", rom_file_name, slot)
+}
 
-    let mut machine = Machine::load(file_name)?;
+fn save_to_slot(machine: &Machine, rom_file_name: &str, slot: u8) -> Result<(), String> {
+    let path = save_slot_path(rom_file_name, slot);
+    fs::write(&path, machine.save_state()).map_err(|_| format!("Write failed to {}", path))
+}
+
+fn load_from_slot(machine: &mut Machine, rom_file_name: &str, slot: u8) -> Result<(), String> {
+    let path = save_slot_path(rom_file_name, slot);
+    let state = fs::read(&path).map_err(|_| format!("Read failed from {}", path))?;
+    keeping_keys(machine, |machine| machine.load_state(&state))
+}
+
+/// Restores a snapshot with `restore`, leaving the keys as they are held on the keyboard
+/// rather than as they were when the snapshot was taken.
+fn keeping_keys<T>(machine: &mut Machine, restore: impl FnOnce(&mut Machine) -> T) -> T {
+    let held: Vec<bool> = (0..16).map(|key| machine.is_key_pressed(key)).collect();
+    let result = restore(machine);
+
+    for (key, held) in (0..16).zip(held) {
+        if held {
+            machine.key_press(key);
+        } else {
+            machine.key_release(key);
+        }
+    }
+    result
+}
+
+/// Saves the screen next to the ROM in the first free `<rom>.screenshotN` file.
+fn save_screenshot(
+    pixel_buffer: &PixelBuffer,
+    rom_file_name: &str,
+    format: ImageFormat,
+    colors: &[[u8; 3]],
+    scale: usize,
+) -> Result<String, String> {
+    let path = (1..)
+        .map(|index| {
+            format!(
+                "{}.screenshot{}.{}",
+                rom_file_name,
+                index,
+                format.extension()
+            )
+        })
+        .find(|path| !Path::new(path).exists())
+        .unwrap();
+
+    pixel_buffer.save_image(&path, format, colors, scale)?;
+    Ok(path)
+}
+
+/// Starts recording to the first free `<rom>.videoN` file next to the ROM.
+fn start_video(
+    rom_file_name: &str,
+    format: VideoFormat,
+    colors: &[[u8; 3]],
+    scale: usize,
+) -> Result<(VideoRecorder, String), String> {
+    let path = (1..)
+        .map(|index| format!("{}.video{}.{}", rom_file_name, index, format.extension()))
+        .find(|path| !Path::new(path).exists())
+        .unwrap();
+
+    let recorder = VideoRecorder::create(&path, format, colors, scale)?;
+    Ok((recorder, path))
+}
+
+/// CHIP-8 key pressed by each physical key, whatever the keyboard layout.
+fn scancode_mappings(keymap: &KeyMap) -> Result<HashMap<Scancode, u8>, String> {
+    keymap
+        .bindings()
+        .iter()
+        .map(|(key, chip8_key)| {
+            Scancode::from_name(key)
+                .map(|scancode| (scancode, *chip8_key))
+                .ok_or(format!("Unknown key {}", key))
+        })
+        .collect()
+}
+
+fn main() -> Result<(), String> {
+    let args = args_with_config(env::args().collect())?;
+
+    let file_name = args.get(1).ok_or(format!(
+        "Usage: chip-8-emulator <rom> {} {} {} {} [--rewind-seconds seconds] \
+         [--record movie | --replay movie] [--window-scale scale] [--scaling integer|fit] [--pixel-aspect width:height] [--fullscreen] \
+         [--anti-flicker off|blend|phosphor[:frames]] [--screenshot-format png|ppm] \
+         [--video file|-] [--video-format gif|raw] [--video-scale scale]",
+        CONFIG_USAGE, MACHINE_OPTIONS_USAGE, PALETTE_OPTIONS_USAGE, KEYMAP_OPTIONS_USAGE
+    ))?;
+
+    // Tab cycles from the palette given on the command line through the built-in themes
+    let palettes: Vec<Palette> = std::iter::once(palette_from_args(&args)?)
+        .chain(THEMES.iter().map(|theme| theme.palette))
+        .collect();
+    let mut palette_index = 0;
+
+    // everything shown or saved goes through the filter, with its shades of the palette
+    let mut flicker =
+        FlickerFilter::new(parse_option(&args, "--anti-flicker")?.unwrap_or_default());
+    let mut colors = flicker.colors(&palettes[palette_index]);
+
+    let mut options = MachineOptions::from_args(&args)?;
+    let rom = fs::read(file_name).map_err(|_| format!("Read failed from {}", file_name))?;
+
+    let mut replay = match option_value(&args, "--replay")? {
+        Some(path) => {
+            let movie = Movie::load(path)?;
+            movie.check_rom(&rom)?;
+            movie.apply_options(&mut options)?;
+            Some(Replay::new(movie))
+        }
+        None => None,
+    };
+    let record_path = option_value(&args, "--record")?;
+    let mut movie = record_path.map(|_| Movie::new(&rom, &options));
+    // rewinding or loading a state would make the movie impossible to replay
+    let time_travel_allowed = replay.is_none() && movie.is_none();
+
+    let mut machine = options.load_machine(file_name)?;
+    let window_scale = parse_option(&args, "--window-scale")?.unwrap_or(DEFAULT_WINDOW_SCALE);
+    if window_scale == 0 {
+        return Err("Window scale must be at least 1".to_string());
+    }
+    let scaling = parse_option(&args, "--scaling")?.unwrap_or(Scaling::Integer);
+    let aspect = parse_option(&args, "--pixel-aspect")?.unwrap_or(PixelAspect::SQUARE);
+    let screenshot_format = parse_option(&args, "--screenshot-format")?.unwrap_or(ImageFormat::Png);
+    let keyboard_mappings = scancode_mappings(&keymap_from_args(&args)?)?;
+
+    // videos default to the initial window size, where a hi-res pixel is half a low-res one
+    let video_format = parse_option(&args, "--video-format")?;
+    let video_scale =
+        parse_option(&args, "--video-scale")?.unwrap_or((window_scale as usize / 2).max(1));
+    let mut video = match option_value(&args, "--video")? {
+        Some(path) => {
+            let format = VideoFormat::resolve(path, video_format)?;
+            Some(VideoRecorder::create(path, format, &colors, video_scale)?)
+        }
+        None => None,
+    };
+
+    let rewind_seconds = parse_option(&args, "--rewind-seconds")?.unwrap_or(DEFAULT_REWIND_SECONDS);
+    let mut rewind_buffer = RewindBuffer::with_seconds(rewind_seconds);
+    let mut rewinding = false;
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -82,56 +344,47 @@ fn main() -> Result<(), String> {
         samples: Some(16), // default sample size
     };
 
-    let audio_device = audio_subsystem
-        .open_playback(None, &desired_spec, |spec| SquareWave {
-            phase_inc: 440.0 / spec.freq as f32,
+    let mut audio_device = audio_subsystem
+        .open_playback(None, &desired_spec, |spec| PatternWave {
+            pattern: *machine.audio_pattern(),
+            sample_rate: spec.freq as f32,
+            phase_inc: machine.audio_playback_rate() / spec.freq as f32,
             phase: 0.0,
             volume: 0.25,
         })
         .unwrap();
 
-    let window = video_subsystem
-        .window(
-            "CHIP-8 Emulator",
-            (NUM_COLS as u32) * SCALE,
-            (NUM_ROWS as u32) * SCALE,
-        )
+    let pixel_height = (window_scale as f64 / aspect.0).round().max(1.0) as u32;
+    let mut window_builder = video_subsystem.window(
+        "CHIP-8 Emulator",
+        (NUM_COLS as u32) * window_scale,
+        (NUM_ROWS as u32) * pixel_height,
+    );
+    window_builder
         .position_centered()
-        .build()
-        .unwrap();
+        .resizable()
+        .allow_highdpi();
+    if has_flag(&args, "--fullscreen") {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().map_err(|error| error.to_string())?;
 
+    // stretched pixels keep hard edges with fit scaling
+    hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
     let mut canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut screen = Screen::new(&texture_creator);
 
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_draw_color(color(colors[0]));
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let keyboard_mappings = hash_map! {
-        Keycode::Right => 0x6u8,
-        Keycode::Down => 0x8,
-        Keycode::Left => 0x4,
-        Keycode::Up => 0x2,
-
-        Keycode::Num1 => 0x1,
-        Keycode::Num2 => 0x2,
-        Keycode::Num3 => 0x3,
-        Keycode::Q => 0x4,
-        Keycode::W => 0x5,
-        Keycode::E => 0x6,
-        Keycode::A => 0x7,
-        Keycode::S => 0x8,
-        Keycode::D => 0x9,
-        Keycode::X => 0x0,
-
-        Keycode::Z => 0xa,
-        Keycode::C => 0xb,
-        Keycode::Num4 => 0xc,
-        Keycode::R => 0xd,
-        Keycode::F => 0xe,
-        Keycode::V => 0xf,
-    };
+    let mut next_frame = Instant::now();
+    let mut presented = machine.get_pixel_buffer().clone();
 
+    // an error stops the loop, so the movie and video recorded up to it are still saved
+    let mut result = Ok(());
     'running: loop {
         for event in event_pump.poll_iter() {
             match event {
@@ -141,21 +394,136 @@ fn main() -> Result<(), String> {
                     ..
                 } => break 'running,
 
-                // TODO: handle key from 0 to F
+                // holding backspace steps backwards one frame at a time
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = time_travel_allowed,
+
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+
+                // Shift+F1-F9 saves to a slot, F1-F9 loads from it
                 Event::KeyDown {
                     keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if save_slot(keycode).is_some() => {
+                    let slot = save_slot(keycode).unwrap();
+                    let result = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        save_to_slot(&machine, file_name, slot)
+                    } else if !time_travel_allowed {
+                        Err("Save states cannot be loaded while recording or replaying".to_string())
+                    } else {
+                        load_from_slot(&mut machine, file_name, slot)
+                    };
+
+                    if let Err(message) = result {
+                        eprintln!("{}", message);
+                    }
+                }
+
+                // F11 and Alt+Enter switch between the window and fullscreen
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keycode == Keycode::F11
+                    || (keycode == Keycode::Return
+                        && keymod.intersects(Mod::LALTMOD | Mod::RALTMOD)) =>
+                {
+                    if let Err(message) = toggle_fullscreen(&mut canvas) {
+                        eprintln!("{}", message);
+                    }
+                }
+
+                // F12 saves a screenshot at the initial window scale, Shift+F12 at native
+                // resolution
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => {
+                    let scale = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        1
+                    } else {
+                        pixel_size(&presented, window_scale)
+                    };
+                    match save_screenshot(&presented, file_name, screenshot_format, &colors, scale)
+                    {
+                        Ok(path) => println!("Saved screenshot to {}", path),
+                        Err(message) => eprintln!("{}", message),
+                    }
+                }
+
+                // F10 starts and stops recording a video
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => match video.take() {
+                    Some(recorder) => match recorder.finish() {
+                        Ok(()) => println!("Stopped recording"),
+                        Err(message) => eprintln!("{}", message),
+                    },
+                    None => {
+                        let format = video_format.unwrap_or(VideoFormat::Gif);
+                        match start_video(file_name, format, &colors, video_scale) {
+                            Ok((recorder, path)) => {
+                                println!("Recording to {}", path);
+                                video = Some(recorder);
+                            }
+                            Err(message) => eprintln!("{}", message),
+                        }
+                    }
+                },
+
+                // Tab switches to the next palette, Shift+Tab to the previous one
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    keymod,
                     ..
                 } => {
-                    if let Some(key) = keyboard_mappings.get(&keycode) {
+                    palette_index = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        (palette_index + palettes.len() - 1) % palettes.len()
+                    } else {
+                        (palette_index + 1) % palettes.len()
+                    };
+                    colors = flicker.colors(&palettes[palette_index]);
+                    if let Some(recorder) = &mut video {
+                        recorder.set_palette(&colors);
+                    }
+                }
+
+                // the keyboard is ignored while a movie drives the keys
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    ..
+                } if replay.is_none() => {
+                    if let Some(key) = keyboard_mappings.get(&scancode) {
+                        if let Some(movie) =
+                            movie.as_mut().filter(|_| !machine.is_key_pressed(*key))
+                        {
+                            movie.record_key(*key, true);
+                        }
                         machine.key_press(*key);
                     }
                 }
 
                 Event::KeyUp {
-                    keycode: Some(keycode),
+                    scancode: Some(scancode),
                     ..
-                } => {
-                    if let Some(key) = keyboard_mappings.get(&keycode) {
+                } if replay.is_none() => {
+                    if let Some(key) = keyboard_mappings.get(&scancode) {
+                        if let Some(movie) = movie.as_mut().filter(|_| machine.is_key_pressed(*key))
+                        {
+                            movie.record_key(*key, false);
+                        }
                         machine.key_release(*key);
                     }
                 }
@@ -164,19 +532,98 @@ fn main() -> Result<(), String> {
             }
         }
 
-        machine.step();
+        if rewinding {
+            if let Err(message) =
+                keeping_keys(&mut machine, |machine| rewind_buffer.rewind(machine))
+            {
+                result = Err(message);
+                break 'running;
+            }
+        } else {
+            if time_travel_allowed {
+                rewind_buffer.capture(&machine);
+            }
+            if let Some(replay) = &mut replay {
+                replay.apply_inputs(&mut machine);
+            }
 
-        draw_pixel_buffer(&mut canvas, machine.get_pixel_buffer())?;
+            if let Err(error) = machine.run_frame(options.instructions_per_frame) {
+                result = Err(error.to_string());
+                break 'running;
+            }
+
+            if let Some(movie) = &mut movie {
+                movie.record_frame(&machine);
+            }
+            if let Some(current) = &mut replay {
+                if let Err(message) = current.end_frame(&machine) {
+                    eprintln!("{}", message);
+                    break 'running;
+                }
+                // hand the keys back to the keyboard once the movie is over
+                if current.is_finished() {
+                    println!("Replay finished after {} frames", current.movie().len());
+                    replay = None;
+                }
+            }
+        }
+
+        if machine.is_halted() {
+            break 'running;
+        }
+
+        presented = flicker.apply(machine.get_pixel_buffer());
+        if let Err(message) = screen.draw(&mut canvas, &presented, &colors, scaling, aspect) {
+            result = Err(message);
+            break 'running;
+        }
+        if let Some(recorder) = &mut video {
+            if let Err(message) = recorder.record_frame(&presented) {
+                result = Err(message);
+                break 'running;
+            }
+        }
 
         if machine.should_beep() {
+            {
+                let mut wave = audio_device.lock();
+                wave.pattern = *machine.audio_pattern();
+                wave.phase_inc = machine.audio_playback_rate() / wave.sample_rate;
+            }
             audio_device.resume();
         } else {
             audio_device.pause();
         }
 
-        // ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
-        ::std::thread::sleep(Duration::from_millis(17));
+        next_frame += FRAME_DURATION;
+        let now = Instant::now();
+        if next_frame > now {
+            ::std::thread::sleep(next_frame - now);
+        } else {
+            // running behind, don't try to catch up on the missed frames
+            next_frame = now;
+        }
     }
 
-    Ok(())
+    let mut finished = Ok(());
+    if let (Some(path), Some(movie)) = (record_path, &movie) {
+        finished = finished.and(movie.save(path));
+    }
+    if let Some(recorder) = video {
+        finished = finished.and(recorder.finish());
+    }
+    finished = finished.and(
+        machine
+            .flush_trace()
+            .map_err(|error| format!("Trace write failed: {}", error)),
+    );
+
+    // the error that stopped the emulator comes first, the one saving after it is printed
+    match (result, finished) {
+        (Err(message), Err(saving)) => {
+            eprintln!("{}", saving);
+            Err(message)
+        }
+        (result, finished) => result.and(finished),
+    }
 }
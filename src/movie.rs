@@ -0,0 +1,448 @@
+//! Input movies: every key event of a run, with what is needed to replay it exactly.
+//!
+//! A movie is a text file:
+//!
+//! ```text
+//! chip-8-movie 1
+//! rom 1a2b3c4d
+//! seed 42
+//! vip-random false
+//! ipf 11
+//! quirks false by-x true true false false
+//! 12 press 5
+//! 20 release 5
+//! hash 0 9f3e0a17
+//! hash 1 0c44d2e8
+//! ```
+//!
+//! `rom` is the CRC-32 of the ROM file. Movies recorded with `vip-random true` also have a
+//! `vip-interpreter` line with the CRC-32 of the interpreter dump the numbers came from.
+//! `quirks` lists, in order, the `Quirks` fields
+//! `shift_uses_vy`, `load_store_increment`, `jump_with_offset_uses_vx`, `clip_sprites`,
+//! `logic_resets_vf` and `display_wait`. Key events use the key script syntax of the `input`
+//! module and apply at the start of their frame. `hash N` is the CRC-32 of the save state
+//! payload at the end of frame N, which lets a replay find the first frame where it diverges.
+
+use std::fs;
+
+use crate::cli::MachineOptions;
+use crate::input::{parse_key_event, KeyEvent};
+use crate::program::Machine;
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::state::crc32;
+
+const HEADER: &str = "chip-8-movie 1";
+
+/// Checksum of everything in a machine, compared frame by frame during replay.
+pub fn state_hash(machine: &Machine) -> u32 {
+    // A save state already ends with the CRC-32 of its payload. Hashing the whole state
+    // again would give the same residue for every state.
+    let state = machine.save_state();
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&state[state.len() - 4..]);
+    u32::from_le_bytes(checksum)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Movie {
+    pub rom_hash: u32,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub seed: u64,
+    pub vip_random: bool,
+    /// CRC-32 of the VIP interpreter dump, when `vip_random` is set.
+    pub vip_interpreter_hash: Option<u32>,
+    /// Key events sorted by frame.
+    pub events: Vec<KeyEvent>,
+    /// State hash at the end of each recorded frame.
+    pub frame_hashes: Vec<u32>,
+}
+
+impl Movie {
+    /// Starts recording a run of `rom` with the machine built from `options`.
+    pub fn new(rom: &[u8], options: &MachineOptions) -> Movie {
+        Movie {
+            rom_hash: crc32(rom),
+            quirks: options.quirks,
+            instructions_per_frame: options.instructions_per_frame,
+            seed: options.seed,
+            vip_random: options.vip_random,
+            vip_interpreter_hash: options
+                .vip_interpreter
+                .as_ref()
+                .filter(|_| options.vip_random)
+                .map(|interpreter| crc32(interpreter)),
+            events: Vec::new(),
+            frame_hashes: Vec::new(),
+        }
+    }
+
+    /// Number of recorded frames.
+    pub fn len(&self) -> u64 {
+        self.frame_hashes.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_hashes.is_empty()
+    }
+
+    /// Records a key event applied before the next frame runs.
+    pub fn record_key(&mut self, key: u8, pressed: bool) {
+        self.events.push(KeyEvent {
+            frame: self.len(),
+            key,
+            pressed,
+        });
+    }
+
+    /// Records the state at the end of a frame.
+    pub fn record_frame(&mut self, machine: &Machine) {
+        self.frame_hashes.push(state_hash(machine));
+    }
+
+    /// Overrides the options that decide how the machine runs with the recorded ones.
+    ///
+    /// Fails when the movie draws random numbers from a VIP interpreter dump other than the
+    /// one given in `options`, since the replay would diverge.
+    pub fn apply_options(&self, options: &mut MachineOptions) -> Result<(), String> {
+        if let Some(expected) = self.vip_interpreter_hash {
+            match options
+                .vip_interpreter
+                .as_ref()
+                .map(|interpreter| crc32(interpreter))
+            {
+                Some(hash) if hash != expected => {
+                    return Err(format!(
+                        "Movie was recorded with VIP interpreter {:08x}, but this one is {:08x}",
+                        expected, hash
+                    ))
+                }
+                Some(_) => {}
+                None => {
+                    return Err(format!(
+                        "Movie needs --vip-interpreter with the dump {:08x}",
+                        expected
+                    ))
+                }
+            }
+        }
+
+        options.quirks = self.quirks;
+        options.instructions_per_frame = self.instructions_per_frame;
+        options.seed = self.seed;
+        options.vip_random = self.vip_random;
+        Ok(())
+    }
+
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), String> {
+        let hash = crc32(rom);
+        if hash != self.rom_hash {
+            return Err(format!(
+                "Movie was recorded with ROM {:08x}, but this ROM is {:08x}",
+                self.rom_hash, hash
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn load(file_name: &str) -> Result<Movie, String> {
+        let text =
+            fs::read_to_string(file_name).map_err(|_| format!("Read failed from {}", file_name))?;
+
+        Movie::parse(&text).map_err(|message| format!("{}: {}", file_name, message))
+    }
+
+    pub fn save(&self, file_name: &str) -> Result<(), String> {
+        fs::write(file_name, self.to_text()).map_err(|_| format!("Write failed to {}", file_name))
+    }
+
+    pub fn to_text(&self) -> String {
+        let quirks = &self.quirks;
+        let increment = match quirks.load_store_increment {
+            IndexIncrement::Unchanged => "unchanged",
+            IndexIncrement::ByX => "by-x",
+            IndexIncrement::ByXPlusOne => "by-x-plus-one",
+        };
+
+        let mut text = format!(
+            "{}\nrom {:08x}\nseed {}\nvip-random {}\nipf {}\nquirks {} {} {} {} {} {}\n",
+            HEADER,
+            self.rom_hash,
+            self.seed,
+            self.vip_random,
+            self.instructions_per_frame,
+            quirks.shift_uses_vy,
+            increment,
+            quirks.jump_with_offset_uses_vx,
+            quirks.clip_sprites,
+            quirks.logic_resets_vf,
+            quirks.display_wait
+        );
+        if let Some(hash) = self.vip_interpreter_hash {
+            text += &format!("vip-interpreter {:08x}\n", hash);
+        }
+        for event in &self.events {
+            text += &format!("{}\n", event);
+        }
+        for (frame, hash) in self.frame_hashes.iter().enumerate() {
+            text += &format!("hash {} {:08x}\n", frame, hash);
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            _ => {
+                return Err(format!(
+                    "Not a movie, expected {} on the first line",
+                    HEADER
+                ))
+            }
+        }
+
+        let mut rom_hash = None;
+        let mut seed = None;
+        let mut vip_random = None;
+        let mut vip_interpreter_hash = None;
+        let mut instructions_per_frame = None;
+        let mut quirks = None;
+        let mut events = Vec::new();
+        let mut frame_hashes = Vec::new();
+
+        for (index, line) in lines {
+            let at_line = |message: &str| format!("Line {}: {}", index + 1, message);
+            let (name, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+
+            match name {
+                "" => {}
+                "rom" => {
+                    let hash = u32::from_str_radix(value, 16);
+                    rom_hash = Some(hash.map_err(|_| at_line("invalid ROM hash"))?);
+                }
+                "seed" => seed = Some(value.parse().map_err(|_| at_line("invalid seed"))?),
+                "vip-random" => {
+                    vip_random = Some(
+                        value
+                            .parse()
+                            .map_err(|_| at_line("expected true or false"))?,
+                    )
+                }
+                "vip-interpreter" => {
+                    let hash = u32::from_str_radix(value, 16);
+                    vip_interpreter_hash =
+                        Some(hash.map_err(|_| at_line("invalid VIP interpreter hash"))?);
+                }
+                "ipf" => {
+                    let ipf = value.parse();
+                    instructions_per_frame = Some(ipf.map_err(|_| at_line("invalid ipf"))?);
+                }
+                "quirks" => quirks = Some(parse_quirks(value).map_err(at_line)?),
+                "hash" => {
+                    let (frame, hash) = value.split_once(' ').ok_or(at_line("expected hash"))?;
+                    if frame.parse::<usize>() != Ok(frame_hashes.len()) {
+                        return Err(at_line("frame hashes must be consecutive from 0"));
+                    }
+                    let hash = u32::from_str_radix(hash, 16);
+                    frame_hashes.push(hash.map_err(|_| at_line("invalid hash"))?);
+                }
+                _ => events.push(parse_key_event(line).map_err(at_line)?),
+            }
+        }
+
+        events.sort_by_key(|event| event.frame);
+        let missing = |name: &str| format!("Movie has no {} line", name);
+        Ok(Movie {
+            rom_hash: rom_hash.ok_or_else(|| missing("rom"))?,
+            quirks: quirks.ok_or_else(|| missing("quirks"))?,
+            instructions_per_frame: instructions_per_frame.ok_or_else(|| missing("ipf"))?,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            vip_random: vip_random.ok_or_else(|| missing("vip-random"))?,
+            vip_interpreter_hash,
+            events,
+            frame_hashes,
+        })
+    }
+}
+
+fn parse_quirks(value: &str) -> Result<Quirks, &'static str> {
+    const INVALID: &str = "expected 6 quirk values";
+
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.len() != 6 {
+        return Err(INVALID);
+    }
+    let flag = |index: usize| fields[index].parse::<bool>().map_err(|_| INVALID);
+
+    Ok(Quirks {
+        shift_uses_vy: flag(0)?,
+        load_store_increment: match fields[1] {
+            "unchanged" => IndexIncrement::Unchanged,
+            "by-x" => IndexIncrement::ByX,
+            "by-x-plus-one" => IndexIncrement::ByXPlusOne,
+            _ => return Err(INVALID),
+        },
+        jump_with_offset_uses_vx: flag(2)?,
+        clip_sprites: flag(3)?,
+        logic_resets_vf: flag(4)?,
+        display_wait: flag(5)?,
+    })
+}
+
+/// Feeds a movie back into a machine built with the movie's options.
+pub struct Replay {
+    movie: Movie,
+    frame: u64,
+    next_event: usize,
+}
+
+impl Replay {
+    pub fn new(movie: Movie) -> Replay {
+        Replay {
+            movie,
+            frame: 0,
+            next_event: 0,
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Applies the key events recorded for the frame about to run.
+    pub fn apply_inputs(&mut self, machine: &mut Machine) {
+        while let Some(event) = self.movie.events.get(self.next_event) {
+            if event.frame > self.frame {
+                break;
+            }
+            event.apply(machine);
+            self.next_event += 1;
+        }
+    }
+
+    /// Checks the state at the end of a frame against the recording.
+    pub fn end_frame(&mut self, machine: &Machine) -> Result<(), String> {
+        let frame = self.frame;
+        self.frame += 1;
+
+        match self.movie.frame_hashes.get(frame as usize) {
+            Some(expected) => {
+                let hash = state_hash(machine);
+                if hash != *expected {
+                    return Err(format!(
+                        "Replay diverged at frame {}: state hash is {:08x}, recorded {:08x}",
+                        frame, hash, expected
+                    ));
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Whether every recorded frame has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Waits for key 5, then stores a random byte in V1 and loops
+    const ROM: [u8; 6] = [0xf0, 0x0a, 0xc1, 0xff, 0x12, 0x04];
+
+    fn options(seed: u64) -> MachineOptions {
+        let mut options = MachineOptions::from_args(&[]).unwrap();
+        options.seed = seed;
+        options
+    }
+
+    fn record(seed: u64) -> Movie {
+        let options = options(seed);
+        let mut movie = Movie::new(&ROM, &options);
+        let mut machine = Machine::from_rom(&ROM, options.quirks)
+            .unwrap()
+            .with_random_source(options.random_source().unwrap());
+
+        for frame in 0..6 {
+            match frame {
+                2 => movie.record_key(5, true),
+                3 => movie.record_key(5, false),
+                _ => {}
+            }
+            if let Some(event) = movie.events.last().filter(|event| event.frame == frame) {
+                event.apply(&mut machine);
+            }
+            machine.run_frame(options.instructions_per_frame).unwrap();
+            movie.record_frame(&machine);
+        }
+        movie
+    }
+
+    fn replay(movie: &Movie) -> Result<(), String> {
+        let options = options(movie.seed);
+        let mut machine = Machine::from_rom(&ROM, options.quirks)
+            .unwrap()
+            .with_random_source(options.random_source().unwrap());
+        let mut replay = Replay::new(movie.clone());
+
+        while !replay.is_finished() {
+            replay.apply_inputs(&mut machine);
+            machine.run_frame(movie.instructions_per_frame).unwrap();
+            replay.end_frame(&machine)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn text_round_trip_test() {
+        let mut movie = record(42);
+        movie.quirks = Quirks::cosmac_vip();
+
+        assert_eq!(movie.events.len(), 2);
+        assert_eq!(Movie::parse(&movie.to_text()), Ok(movie));
+        assert!(Movie::parse("chip-8-movie 1\nrom 0").is_err());
+        assert!(Movie::parse("not a movie").is_err());
+    }
+
+    #[test]
+    fn replay_test() {
+        let mut movie = record(42);
+        assert_eq!(replay(&movie), Ok(()));
+
+        // Holding the key one frame longer changes the state from frame 3 on
+        movie.events[1].frame = 4;
+        assert_eq!(
+            replay(&movie).unwrap_err().split(':').next(),
+            Some("Replay diverged at frame 3")
+        );
+    }
+
+    #[test]
+    fn vip_interpreter_test() {
+        let mut options = options(42);
+        options.vip_random = true;
+        options.vip_interpreter = Some(vec![1; 0x200]);
+        let movie = Movie::new(&ROM, &options);
+
+        assert_eq!(movie.vip_interpreter_hash, Some(crc32(&[1; 0x200])));
+        assert_eq!(Movie::parse(&movie.to_text()), Ok(movie.clone()));
+        assert!(movie.apply_options(&mut options).is_ok());
+
+        options.vip_interpreter = Some(vec![2; 0x200]);
+        assert!(movie.apply_options(&mut options).is_err());
+        options.vip_interpreter = None;
+        assert!(movie.apply_options(&mut options).is_err());
+    }
+
+    #[test]
+    fn check_rom_test() {
+        let movie = record(42);
+
+        assert!(movie.check_rom(&ROM).is_ok());
+        assert!(movie.check_rom(&[0x12, 0x00]).is_err());
+    }
+}
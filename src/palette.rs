@@ -0,0 +1,140 @@
+//! Colours of the four pixel colour indices and the built-in themes.
+
+/// RGB colour of each colour index: background, first plane, second plane, both planes.
+pub type Palette = [[u8; 3]; 4];
+
+pub const DEFAULT_PALETTE: Palette = [[0, 0, 0], [255, 255, 255], [255, 102, 0], [102, 34, 0]];
+
+pub struct Theme {
+    pub name: &'static str,
+    pub palette: Palette,
+}
+
+/// Built-in themes, in the order the SDL frontend cycles through them.
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "default",
+        palette: DEFAULT_PALETTE,
+    },
+    Theme {
+        name: "green-phosphor",
+        palette: [
+            [0x05, 0x14, 0x08],
+            [0x33, 0xff, 0x66],
+            [0x1a, 0x80, 0x33],
+            [0xb0, 0xff, 0xc0],
+        ],
+    },
+    Theme {
+        name: "amber",
+        palette: [
+            [0x14, 0x0c, 0x00],
+            [0xff, 0xb0, 0x00],
+            [0x80, 0x58, 0x00],
+            [0xff, 0xe0, 0x90],
+        ],
+    },
+    // shades of the original Game Boy screen, lightest as the background. Its second lightest
+    // shade is too close to the background to draw with, so both planes get a mid green.
+    Theme {
+        name: "gameboy",
+        palette: [
+            [0x9b, 0xbc, 0x0f],
+            [0x0f, 0x38, 0x0f],
+            [0x30, 0x62, 0x30],
+            [0x5f, 0x8a, 0x1f],
+        ],
+    },
+    Theme {
+        name: "high-contrast",
+        palette: [
+            [0x00, 0x00, 0x00],
+            [0xff, 0xff, 0xff],
+            [0xff, 0xff, 0x00],
+            [0x00, 0xff, 0xff],
+        ],
+    },
+    // Okabe-Ito colours, which stay distinct with every common colour vision deficiency
+    Theme {
+        name: "colorblind",
+        palette: [
+            [0x00, 0x00, 0x00],
+            [0x56, 0xb4, 0xe9],
+            [0xe6, 0x9f, 0x00],
+            [0xf0, 0xe4, 0x42],
+        ],
+    },
+    Theme {
+        name: "colorblind-light",
+        palette: [
+            [0xff, 0xff, 0xff],
+            [0x00, 0x00, 0x00],
+            [0x00, 0x72, 0xb2],
+            [0xd5, 0x5e, 0x00],
+        ],
+    },
+];
+
+/// Parses a colour written as six hex digits, optionally preceded by `#`.
+pub fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let digits = color.strip_prefix('#').unwrap_or(color);
+    let invalid = || format!("Invalid colour {}, expected RRGGBB in hex", color);
+
+    if digits.len() != 6 || !digits.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |index: usize| u8::from_str_radix(&digits[index..index + 2], 16);
+
+    match (channel(0), channel(2), channel(4)) {
+        (Ok(red), Ok(green), Ok(blue)) => Ok([red, green, blue]),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses a theme name, or four comma separated colours for the four colour indices.
+pub fn parse_palette(palette: &str) -> Result<Palette, String> {
+    if let Some(theme) = THEMES.iter().find(|theme| theme.name == palette) {
+        return Ok(theme.palette);
+    }
+    if !palette.contains(',') {
+        let names: Vec<&str> = THEMES.iter().map(|theme| theme.name).collect();
+        return Err(format!(
+            "Unknown palette {}, expected one of {} or four colours",
+            palette,
+            names.join(", ")
+        ));
+    }
+
+    let colors = palette
+        .split(',')
+        .map(|color| parse_color(color.trim()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match colors.as_slice() {
+        [background, first, second, both] => Ok([*background, *first, *second, *both]),
+        _ => Err(format!("Palette {} must have four colours", palette)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_color_test() {
+        assert_eq!(parse_color("#33ff66"), Ok([0x33, 0xff, 0x66]));
+        assert_eq!(parse_color("FFB000"), Ok([0xff, 0xb0, 0x00]));
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("12345g").is_err());
+    }
+
+    #[test]
+    fn parse_palette_test() {
+        assert_eq!(parse_palette("gameboy"), Ok(THEMES[3].palette));
+        assert_eq!(
+            parse_palette("000000, ffffff, #ff0000,00ff00"),
+            Ok([[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 255, 0]])
+        );
+        assert!(parse_palette("sepia").is_err());
+        assert!(parse_palette("000000,ffffff").is_err());
+    }
+}
@@ -1,18 +1,30 @@
-#[path = "./instruction.rs"]
-mod instruction;
-use std::fs;
+use std::{fs, io, ops::Range};
 
-use instruction::{parse_opcode, Instruction};
+use crate::display::{
+    PixelBuffer, FIRST_PLANE, HIRES_NUM_COLS, HIRES_NUM_ROWS, NUM_COLS, NUM_ROWS,
+};
+use crate::error::MachineError;
+use crate::instruction::{parse_opcode, Instruction};
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::random::{RandomSource, SeededRandom};
+use crate::state::{self, StateReader, StateWriter};
+use crate::trace::{RegisterSnapshot, TraceRecord, Tracer};
 
-pub const NUM_ROWS: usize = 32;
-pub const NUM_COLS: usize = 64;
-
-const MEMORY_SIZE: usize = 4096;
-const PROGRAM_STARTING_ADDRESS: u16 = 512;
+pub const MEMORY_SIZE: usize = 0x10000;
+pub const PROGRAM_STARTING_ADDRESS: u16 = 512;
 const FONT_STARTING_ADDRESS: usize = 0x50;
 const FONT_BYTES: u16 = 5;
+const BIG_FONT_STARTING_ADDRESS: usize = 0xa0;
+const BIG_FONT_BYTES: u16 = 10;
+
+pub const AUDIO_PATTERN_BYTES: usize = 16;
+const DEFAULT_PITCH: u8 = 64;
+const STACK_SIZE: usize = 16;
 
-fn copy_font_data(memory: &mut [u8; MEMORY_SIZE]) {
+/// Square wave played until a program loads its own pattern, about 500 Hz at the default pitch.
+const DEFAULT_AUDIO_PATTERN: [u8; AUDIO_PATTERN_BYTES] = [0xf0; AUDIO_PATTERN_BYTES];
+
+fn copy_font_data(memory: &mut [u8]) {
     let font_data: Vec<u8> = vec![
         0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
         0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -32,15 +44,67 @@ fn copy_font_data(memory: &mut [u8; MEMORY_SIZE]) {
         0xF0, 0x80, 0xF0, 0x80, 0x80, // F
     ];
 
-    for index in 0..font_data.len() {
-        memory[FONT_STARTING_ADDRESS + index] = font_data[index];
-    }
+    memory[FONT_STARTING_ADDRESS..FONT_STARTING_ADDRESS + font_data.len()]
+        .copy_from_slice(&font_data);
+
+    let big_font_data: Vec<u8> = vec![
+        0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+        0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+        0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+        0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+        0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+        0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+        0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+        0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+        0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+        0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+    ];
+
+    memory[BIG_FONT_STARTING_ADDRESS..BIG_FONT_STARTING_ADDRESS + big_font_data.len()]
+        .copy_from_slice(&big_font_data);
+}
+
+/// What a call to `Machine::step` did.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum StepOutcome {
+    /// The instruction was executed.
+    Executed(Instruction),
+    /// Nothing was executed because DXYN is waiting for the next vertical blank.
+    WaitingForDisplay,
+    /// Nothing was executed because the program exited through 00FD.
+    Halted,
+}
+
+/// A fault in an instruction, turned into a `MachineError` once the faulting opcode is known.
+enum Fault {
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfRange { target: usize, accessed: usize },
 }
 
-pub type PixelBuffer = [[bool; NUM_COLS as usize]; NUM_ROWS as usize];
+impl Fault {
+    fn into_error(self, address: u16, opcode: u16) -> MachineError {
+        match self {
+            Fault::StackOverflow => MachineError::StackOverflow { address, opcode },
+            Fault::StackUnderflow => MachineError::StackUnderflow { address, opcode },
+            Fault::MemoryOutOfRange { target, accessed } => MachineError::MemoryOutOfRange {
+                address,
+                opcode,
+                target,
+                accessed,
+            },
+        }
+    }
+}
 
 pub struct Machine {
-    memory: [u8; MEMORY_SIZE],
+    memory: Vec<u8>,
     program_counter: u16,
 
     registers: [u8; 16],
@@ -49,20 +113,43 @@ pub struct Machine {
     i: u16,
     pixel_buffer: PixelBuffer,
     stack: Vec<u16>,
+    rpl_flags: [u8; 16],
+    halted: bool,
+    selected_planes: u8,
+    audio_pattern: [u8; AUDIO_PATTERN_BYTES],
+    pitch: u8,
+
+    // bit N is set while key N is held down
+    pressed_keys: u16,
+    // key pressed during FX0A, which completes once it is released
+    awaited_key: Option<u8>,
 
-    current_pressed_key: Option<u8>,
+    quirks: Quirks,
+    waiting_for_vblank: bool,
+    random: Box<dyn RandomSource>,
+    tracer: Option<Box<dyn Tracer>>,
 }
 
 impl Machine {
-    pub fn load(file_name: &String) -> Result<Machine, String> {
+    pub fn load(file_name: &str, quirks: Quirks) -> Result<Machine, String> {
         let bytes = fs::read(file_name).map_err(|_| format!("Read failed from {}", file_name))?;
 
-        let mut memory = [0 as u8; MEMORY_SIZE];
+        Machine::from_rom(&bytes, quirks)
+    }
 
-        for index in 0..bytes.len() {
-            memory[PROGRAM_STARTING_ADDRESS as usize + index] = bytes[index];
+    pub fn from_rom(rom: &[u8], quirks: Quirks) -> Result<Machine, String> {
+        let start = PROGRAM_STARTING_ADDRESS as usize;
+        if rom.len() > MEMORY_SIZE - start {
+            return Err(format!(
+                "ROM is {} bytes, but only {} bytes are available",
+                rom.len(),
+                MEMORY_SIZE - start
+            ));
         }
 
+        let mut memory = vec![0u8; MEMORY_SIZE];
+        memory[start..start + rom.len()].copy_from_slice(rom);
+
         copy_font_data(&mut memory);
 
         Ok(Machine {
@@ -70,18 +157,48 @@ impl Machine {
             program_counter: PROGRAM_STARTING_ADDRESS,
             registers: [0; 16],
             i: 0,
-            pixel_buffer: [[false; NUM_COLS]; NUM_ROWS],
+            pixel_buffer: PixelBuffer::lowres(),
             stack: Vec::new(),
-            current_pressed_key: None,
+            rpl_flags: [0; 16],
+            halted: false,
+            selected_planes: FIRST_PLANE,
+            audio_pattern: DEFAULT_AUDIO_PATTERN,
+            pitch: DEFAULT_PITCH,
+            pressed_keys: 0,
+            awaited_key: None,
             delay_timer: 0,
             sound_timer: 0,
+            quirks,
+            waiting_for_vblank: false,
+            random: Box::new(SeededRandom::from_entropy()),
+            tracer: None,
         })
     }
 
-    fn handle_instruction(&mut self, instruction: Instruction) {
+    /// Replaces the entropy seeded generator used by CXNN, for reproducible runs.
+    pub fn with_random_source(mut self, random: Box<dyn RandomSource>) -> Machine {
+        self.random = random;
+        self
+    }
+
+    /// Reports every executed instruction to `tracer`.
+    pub fn with_tracer(mut self, tracer: Box<dyn Tracer>) -> Machine {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Writes out buffered trace records, if tracing.
+    pub fn flush_trace(&mut self) -> io::Result<()> {
+        match &mut self.tracer {
+            Some(tracer) => tracer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn handle_instruction(&mut self, instruction: Instruction) -> Result<(), Fault> {
         match instruction {
             Instruction::ClearScreen => {
-                self.pixel_buffer = [[false; 64]; 32];
+                self.pixel_buffer.clear(self.selected_planes);
             }
 
             Instruction::StoreAddrToI(addr) => {
@@ -97,52 +214,72 @@ impl Machine {
                 register_y,
                 bytes,
             } => {
-                let x = self.registers[register_x as usize] % (NUM_COLS as u8);
-                let mut y = self.registers[register_y as usize] % (NUM_ROWS as u8);
+                let width = self.pixel_buffer.width();
+                let height = self.pixel_buffer.height();
+
+                let x = self.registers[register_x as usize] as usize % width;
+                let y = self.registers[register_y as usize] as usize % height;
+
+                // DXY0 draws a 16x16 sprite stored as two bytes per row
+                let (sprite_width, sprite_height) = if bytes == 0 { (16, 16) } else { (8, bytes) };
+                let bytes_per_row = sprite_width / 8;
+                let bytes_per_plane = bytes_per_row * sprite_height as usize;
 
                 // println!("start drawing at {}, {}", x, y);
 
                 // try using self.vf to simplify the code
                 self.registers[0xf] = 0;
 
-                for index in 0..bytes {
-                    if y >= 32 {
-                        break;
+                // with both planes selected the sprite for the second plane follows the first
+                let mut sprite_start = self.i as usize;
+                for plane in [0b01, 0b10] {
+                    if self.selected_planes & plane == 0 {
+                        continue;
                     }
 
-                    let location = self.i as usize + index as usize;
-                    let sprite_bytes = self.memory[location];
+                    for index in 0..sprite_height as usize {
+                        let mut current_y = y + index;
+                        if current_y >= height {
+                            if self.quirks.clip_sprites {
+                                break;
+                            }
+                            current_y %= height;
+                        }
 
-                    // println!(
-                    //     "extracting sprite at {:02x?}, value: {:#08b}",
-                    //     location, sprite_bytes
-                    // );
+                        let location = sprite_start + index * bytes_per_row;
+                        let sprite_row = self.memory[self.memory_range(location, bytes_per_row)?]
+                            .iter()
+                            .fold(0u16, |row, byte| (row << 8) | *byte as u16);
 
-                    let mut current_x = x;
-                    for col in 0..8 {
-                        if current_x >= 64 {
-                            break;
-                        }
+                        // println!(
+                        //     "extracting sprite at {:02x?}, value: {:#018b}",
+                        //     location, sprite_row
+                        // );
 
-                        let address = &mut self.pixel_buffer[y as usize][current_x as usize];
-                        let pixel_value = *address as u8;
+                        for col in 0..sprite_width {
+                            let mut current_x = x + col;
+                            if current_x >= width {
+                                if self.quirks.clip_sprites {
+                                    break;
+                                }
+                                current_x %= width;
+                            }
 
-                        let sprite_value = sprite_bytes & (1 << (7 - col));
+                            let sprite_value = sprite_row & (1 << (sprite_width - 1 - col));
 
-                        if sprite_value != 0 {
-                            if pixel_value == 1 {
-                                *address = false;
+                            if sprite_value != 0
+                                && self.pixel_buffer.toggle(current_x, current_y, plane)
+                            {
                                 self.registers[0xf] = 1;
-                            } else {
-                                // println!("turn on at {}, {}", current_x, y);
-                                *address = true;
                             }
                         }
-
-                        current_x += 1;
                     }
 
-                    y += 1
+                    sprite_start += bytes_per_plane;
+                }
+
+                if self.quirks.display_wait {
+                    self.waiting_for_vblank = true;
                 }
             }
 
@@ -157,29 +294,31 @@ impl Machine {
 
             Instruction::SkipIfNotEqual { register, value } => {
                 if self.registers[register as usize] != value {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
 
             Instruction::AddRegisterToI(register) => {
-                self.i += self.registers[register as usize] as u16;
+                self.i = self
+                    .i
+                    .wrapping_add(self.registers[register as usize] as u16);
             }
 
             Instruction::CallSubroutineAtAddress(address) => {
+                if self.stack.len() >= STACK_SIZE {
+                    return Err(Fault::StackOverflow);
+                }
                 self.stack.push(self.program_counter);
                 self.program_counter = address;
             }
 
             Instruction::ReturnFromSubroutine => {
-                let return_address = self
-                    .stack
-                    .pop()
-                    .expect("Returning from subroutine, but the stack is empty");
+                let return_address = self.stack.pop().ok_or(Fault::StackUnderflow)?;
                 self.program_counter = return_address;
             }
             Instruction::SkipIfEqual { register, value } => {
                 if self.registers[register as usize] == value {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
             Instruction::StoreYToX {
@@ -190,7 +329,7 @@ impl Machine {
             }
 
             Instruction::SetRandomNumber { register, mask } => {
-                let value = fastrand::u8(..) & mask;
+                let value = self.random.next_u8() & mask;
                 self.registers[register as usize] = value;
             }
             Instruction::SkipIfRegistersEqual {
@@ -198,7 +337,7 @@ impl Machine {
                 register_y,
             } => {
                 if self.registers[register_x as usize] == self.registers[register_y as usize] {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
             Instruction::OrRegisters {
@@ -206,18 +345,27 @@ impl Machine {
                 register_y,
             } => {
                 self.registers[register_x as usize] |= self.registers[register_y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xf] = 0;
+                }
             }
             Instruction::AndRegisters {
                 register_x,
                 register_y,
             } => {
                 self.registers[register_x as usize] &= self.registers[register_y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xf] = 0;
+                }
             }
             Instruction::XorRegisters {
                 register_x,
                 register_y,
             } => {
                 self.registers[register_x as usize] ^= self.registers[register_y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0xf] = 0;
+                }
             }
             Instruction::AddRegisters {
                 register_x,
@@ -226,7 +374,7 @@ impl Machine {
                 let value_x = self.registers[register_x as usize];
                 let value_y = self.registers[register_y as usize];
 
-                self.registers[0xf] = if value_x.checked_add(value_y) == None {
+                self.registers[0xf] = if value_x.checked_add(value_y).is_none() {
                     1
                 } else {
                     0
@@ -258,51 +406,54 @@ impl Machine {
                 register_y,
             } => {
                 if self.registers[register_x as usize] != self.registers[register_y as usize] {
-                    self.program_counter += 2;
+                    self.skip_next_instruction();
                 }
             }
             Instruction::ShiftRegisterLeft {
                 register_x,
-                register_y: _,
+                register_y,
             } => {
-                // keypad test requires the CHIP48 behavior
-                let value_y = self.registers[register_x as usize];
+                let value = self.shift_source(register_x, register_y);
 
-                self.registers[0xf] = value_y & (1 << 7);
-                self.registers[register_x as usize] <<= 1;
+                self.registers[register_x as usize] = value << 1;
+                self.registers[0xf] = value >> 7;
             }
             Instruction::ShiftRegisterRight {
                 register_x,
-                register_y: _,
+                register_y,
             } => {
-                // keypad test requires the CHIP48 behavior
-                let value_y = self.registers[register_x as usize];
+                let value = self.shift_source(register_x, register_y);
 
-                self.registers[0xf] = value_y & 1;
-                self.registers[register_x as usize] >>= 1;
+                self.registers[register_x as usize] = value >> 1;
+                self.registers[0xf] = value & 1;
             }
             Instruction::LoadRegisters(final_register) => {
-                for register in 0..=final_register {
-                    self.registers[register as usize] =
-                        self.memory[self.i as usize + register as usize];
-                }
-                // self.i += final_register as u16 + 1;
+                let count = final_register as usize + 1;
+                let range = self.memory_range(self.i as usize, count)?;
+                self.registers[..count].copy_from_slice(&self.memory[range]);
+                self.increment_i_after_load_store(final_register);
             }
             Instruction::SaveRegisters(final_register) => {
-                for register in 0..=final_register {
-                    self.memory[self.i as usize + register as usize] =
-                        self.registers[register as usize];
-                }
-
-                // self.i += final_register as u16 + 1;
+                let count = final_register as usize + 1;
+                let range = self.memory_range(self.i as usize, count)?;
+                self.memory[range].copy_from_slice(&self.registers[..count]);
+                self.increment_i_after_load_store(final_register);
             }
             Instruction::SetIToFontLocation(register) => {
                 let font_character = self.registers[register as usize] as u16;
                 self.i = FONT_STARTING_ADDRESS as u16 + FONT_BYTES * font_character;
             }
-            Instruction::HaltAndGetKey(register) => match self.current_pressed_key {
-                None => self.program_counter -= 2,
-                Some(key) => self.registers[register as usize] = key,
+            Instruction::HaltAndGetKey(register) => match self.awaited_key {
+                Some(key) if !self.is_key_pressed(key) => {
+                    self.registers[register as usize] = key;
+                    self.awaited_key = None;
+                }
+                _ => {
+                    if self.awaited_key.is_none() && self.pressed_keys != 0 {
+                        self.awaited_key = Some(self.pressed_keys.trailing_zeros() as u8);
+                    }
+                    self.program_counter = self.program_counter.wrapping_sub(2);
+                }
             },
             Instruction::SetDelayTimerFromRegister(register) => {
                 self.delay_timer = self.registers[register as usize];
@@ -314,67 +465,819 @@ impl Machine {
                 self.sound_timer = self.registers[register as usize];
             }
             Instruction::BinaryRepresentationFromRegister(register) => {
-                let mut value = self.registers[register as usize];
-
-                self.memory[self.i as usize + 2] = value % 10;
-                value /= 10;
-
-                self.memory[self.i as usize + 1] = value % 10;
-                value /= 10;
+                let value = self.registers[register as usize];
+                let range = self.memory_range(self.i as usize, 3)?;
 
-                self.memory[self.i as usize] = value;
+                self.memory[range].copy_from_slice(&[value / 100, value / 10 % 10, value % 10]);
             }
             Instruction::SkipIfPressedKeyContainsRegisterValue(register) => {
                 let value = self.registers[register as usize];
 
-                if self.current_pressed_key == Some(value) {
-                    self.program_counter += 2;
+                if self.is_key_pressed(value) {
+                    self.skip_next_instruction();
                 }
             }
             Instruction::SkipIfPressedKeyDoesNotContainsRegisterValue(register) => {
                 let value = self.registers[register as usize];
 
-                if self.current_pressed_key != Some(value) {
-                    self.program_counter += 2;
+                if !self.is_key_pressed(value) {
+                    self.skip_next_instruction();
                 }
             }
             Instruction::JumpWithOffset(offset) => {
-                self.program_counter = offset + self.registers[0x0] as u16;
+                let register = if self.quirks.jump_with_offset_uses_vx {
+                    (offset >> 8) as usize
+                } else {
+                    0x0
+                };
+                self.program_counter = offset + self.registers[register] as u16;
+            }
+            Instruction::ScrollDown(rows) => {
+                self.pixel_buffer
+                    .scroll_down(rows as usize, self.selected_planes);
+            }
+            Instruction::ScrollUp(rows) => {
+                self.pixel_buffer
+                    .scroll_up(rows as usize, self.selected_planes);
+            }
+            Instruction::ScrollRight => {
+                self.pixel_buffer.scroll_right(4, self.selected_planes);
+            }
+            Instruction::ScrollLeft => {
+                self.pixel_buffer.scroll_left(4, self.selected_planes);
+            }
+            Instruction::Exit => {
+                self.halted = true;
+            }
+            Instruction::LowResolution => {
+                self.pixel_buffer = PixelBuffer::lowres();
+            }
+            Instruction::HighResolution => {
+                self.pixel_buffer = PixelBuffer::hires();
+            }
+            Instruction::SetIToBigFontLocation(register) => {
+                let font_character = self.registers[register as usize] as u16;
+                self.i = BIG_FONT_STARTING_ADDRESS as u16 + BIG_FONT_BYTES * font_character;
+            }
+            Instruction::SaveFlags(final_register) => {
+                let count = final_register as usize + 1;
+                self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+            }
+            Instruction::LoadFlags(final_register) => {
+                let count = final_register as usize + 1;
+                self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+            }
+            Instruction::SaveRegisterRange {
+                register_x,
+                register_y,
+            } => {
+                let start = self
+                    .memory_range(
+                        self.i as usize,
+                        register_x.abs_diff(register_y) as usize + 1,
+                    )?
+                    .start;
+                for (offset, register) in register_range(register_x, register_y).enumerate() {
+                    self.memory[start + offset] = self.registers[register as usize];
+                }
+            }
+            Instruction::LoadRegisterRange {
+                register_x,
+                register_y,
+            } => {
+                let start = self
+                    .memory_range(
+                        self.i as usize,
+                        register_x.abs_diff(register_y) as usize + 1,
+                    )?
+                    .start;
+                for (offset, register) in register_range(register_x, register_y).enumerate() {
+                    self.registers[register as usize] = self.memory[start + offset];
+                }
+            }
+            Instruction::StoreNextWordToI => {
+                let range = self.memory_range(self.program_counter as usize, 2)?;
+                self.i =
+                    u16::from_be_bytes([self.memory[range.start], self.memory[range.start + 1]]);
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::SelectPlanes(planes) => {
+                self.selected_planes = planes;
+            }
+            Instruction::LoadAudioPattern => {
+                let range = self.memory_range(self.i as usize, AUDIO_PATTERN_BYTES)?;
+                self.audio_pattern.copy_from_slice(&self.memory[range]);
             }
+            Instruction::SetPitchFromRegister(register) => {
+                self.pitch = self.registers[register as usize];
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Range of `len` bytes of memory starting at `start`, if all of them exist.
+    fn memory_range(&self, start: usize, len: usize) -> Result<Range<usize>, Fault> {
+        if start + len > self.memory.len() {
+            return Err(Fault::MemoryOutOfRange {
+                target: start,
+                accessed: len,
+            });
+        }
+
+        Ok(start..start + len)
+    }
+
+    /// The opcode at `address`, or `None` when it runs past the end of memory.
+    pub fn opcode_at(&self, address: u16) -> Option<u16> {
+        let address = address as usize;
+        let bytes = self.memory.get(address..address + 2)?;
+
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Skips the next instruction, which is two words long when it is F000 NNNN.
+    fn skip_next_instruction(&mut self) {
+        let next_opcode = self.opcode_at(self.program_counter);
+        let length = if next_opcode == Some(0xf000) { 4 } else { 2 };
+
+        self.program_counter = self.program_counter.wrapping_add(length);
+    }
+
+    fn shift_source(&self, register_x: u8, register_y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[register_y as usize]
+        } else {
+            self.registers[register_x as usize]
+        }
+    }
+
+    fn increment_i_after_load_store(&mut self, final_register: u8) {
+        match self.quirks.load_store_increment {
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::ByX => self.i = self.i.wrapping_add(final_register as u16),
+            IndexIncrement::ByXPlusOne => self.i = self.i.wrapping_add(final_register as u16 + 1),
         }
     }
 
-    pub fn step(&mut self) {
-        let a = self.memory[self.program_counter as usize];
-        let b = self.memory[self.program_counter as usize + 1];
+    /// Executes the instruction at the program counter.
+    ///
+    /// On error the machine is left as it was when the fault happened, with the program
+    /// counter already past the faulting instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, MachineError> {
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+        if self.waiting_for_vblank {
+            return Ok(StepOutcome::WaitingForDisplay);
+        }
 
-        let opcode = ((a as u16) << 8) | b as u16;
+        let address = self.program_counter;
+        let opcode = self
+            .opcode_at(address)
+            .ok_or(MachineError::ProgramCounterOutOfRange { address })?;
 
         let instruction = parse_opcode(opcode);
-        self.program_counter += 2;
-        println!("instruction: {:#04x?}, opcode {:02x?}", opcode, instruction);
+        self.program_counter = self.program_counter.wrapping_add(2);
+
+        let instruction = instruction.ok_or(MachineError::UnknownOpcode { address, opcode })?;
+
+        let before = self.tracer.as_ref().map(|_| RegisterSnapshot::of(self));
+        self.handle_instruction(instruction)
+            .map_err(|fault| fault.into_error(address, opcode))?;
+
+        if let Some(before) = before {
+            let record = TraceRecord {
+                address,
+                opcode,
+                instruction,
+                quirks: self.quirks,
+                before,
+                after: RegisterSnapshot::of(self),
+            };
+            if let Some(tracer) = &mut self.tracer {
+                tracer.record(&record);
+            }
+        }
+
+        Ok(StepOutcome::Executed(instruction))
+    }
+
+    /// Runs one 60 Hz frame: executes up to `instructions_per_frame` instructions, then ticks
+    /// the delay and sound timers once.
+    ///
+    /// The frame ends early when DXYN waits for the display or the program exits.
+    pub fn run_frame(&mut self, instructions_per_frame: u32) -> Result<(), MachineError> {
+        for _ in 0..instructions_per_frame {
+            if let StepOutcome::WaitingForDisplay | StepOutcome::Halted = self.step()? {
+                break;
+            }
+        }
+
+        self.end_frame();
+
+        Ok(())
+    }
+
+    /// Ends a 60 Hz frame: ticks the timers and releases a DXYN waiting for the display.
+    ///
+    /// Only needed when driving the machine with `step` instead of `run_frame`.
+    pub fn end_frame(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.waiting_for_vblank = false;
+        self.random.frame();
+    }
+
+    /// Snapshot of the whole machine in the versioned format described in the `state` module.
+    ///
+    /// The quirks profile is configuration rather than state and is not included.
+    pub fn save_state(&self) -> Vec<u8> {
+        state::encode(&self.state_payload())
+    }
+
+    /// The payload of `save_state`, without the header and checksum.
+    pub(crate) fn state_payload(&self) -> Vec<u8> {
+        let mut writer = StateWriter::default();
+
+        writer.sized_bytes(&self.memory);
+        writer.u16(self.program_counter);
+        writer.bytes(&self.registers);
+        writer.u16(self.i);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+
+        writer.u8(self.stack.len() as u8);
+        for address in &self.stack {
+            writer.u16(*address);
+        }
+
+        writer.bytes(&self.rpl_flags);
+        writer.bool(self.halted);
+        writer.u8(self.selected_planes);
+        writer.bytes(&self.audio_pattern);
+        writer.u8(self.pitch);
+        writer.u16(self.pressed_keys);
+        writer.u8(self.awaited_key.unwrap_or(0xff));
+        writer.bool(self.waiting_for_vblank);
+
+        writer.u16(self.pixel_buffer.width() as u16);
+        writer.u16(self.pixel_buffer.height() as u16);
+        writer.bytes(self.pixel_buffer.pixels());
+
+        writer.sized_bytes(&self.random.save_state());
+
+        writer.finish()
+    }
+
+    /// Restores a snapshot made by `save_state`. The machine is left untouched on error.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.load_state_payload(state::decode(state)?)
+    }
+
+    /// Restores a payload made by `state_payload`. The machine is left untouched on error.
+    pub(crate) fn load_state_payload(&mut self, payload: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader::new(payload);
+
+        let memory = reader.sized_bytes()?;
+        if memory.len() != MEMORY_SIZE {
+            return Err(format!(
+                "Save state has {} bytes of memory, expected {}",
+                memory.len(),
+                MEMORY_SIZE
+            ));
+        }
 
-        self.delay_timer = self.delay_timer.checked_sub(1).unwrap_or(0);
-        self.sound_timer = self.sound_timer.checked_sub(1).unwrap_or(0);
+        let program_counter = reader.u16()?;
+        let mut registers = [0; 16];
+        registers.copy_from_slice(reader.bytes(16)?);
+        let i = reader.u16()?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
 
-        instruction
-            .map(move |instruction| self.handle_instruction(instruction))
-            .expect(format!("Failed to translate opcode: {:#02x?}, either the opcode is not supported yet, or there is a bug in the interpreter", opcode).as_str());
+        let depth = reader.u8()? as usize;
+        if depth > STACK_SIZE {
+            return Err(format!("Save state has a stack {} levels deep", depth));
+        }
+        let stack = (0..depth)
+            .map(|_| reader.u16())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut rpl_flags = [0; 16];
+        rpl_flags.copy_from_slice(reader.bytes(16)?);
+        let halted = reader.bool()?;
+        let selected_planes = reader.u8()?;
+        if selected_planes > 0b11 {
+            return Err(format!(
+                "Save state selects planes {:#04b}, expected a mask of two planes",
+                selected_planes
+            ));
+        }
+        let mut audio_pattern = [0; AUDIO_PATTERN_BYTES];
+        audio_pattern.copy_from_slice(reader.bytes(AUDIO_PATTERN_BYTES)?);
+        let pitch = reader.u8()?;
+        let pressed_keys = reader.u16()?;
+        let awaited_key = match reader.u8()? {
+            0xff => None,
+            key => Some(key),
+        };
+        let waiting_for_vblank = reader.bool()?;
+
+        let width = reader.u16()? as usize;
+        let height = reader.u16()? as usize;
+        if (width, height) != (NUM_COLS, NUM_ROWS)
+            && (width, height) != (HIRES_NUM_COLS, HIRES_NUM_ROWS)
+        {
+            return Err(format!(
+                "Save state has a {}x{} display, expected {}x{} or {}x{}",
+                width, height, NUM_COLS, NUM_ROWS, HIRES_NUM_COLS, HIRES_NUM_ROWS
+            ));
+        }
+        let pixels = reader.bytes(width * height)?.to_vec();
+        if let Some(pixel) = pixels.iter().find(|pixel| **pixel > 0b11) {
+            return Err(format!("Save state has a pixel of colour {}", pixel));
+        }
+        let pixel_buffer = PixelBuffer::from_pixels(width, height, pixels)
+            .ok_or("Save state has an invalid pixel buffer")?;
+
+        let random_state = reader.sized_bytes()?;
+        reader.finish()?;
+
+        self.random.load_state(random_state)?;
+
+        self.memory.copy_from_slice(memory);
+        self.program_counter = program_counter;
+        self.registers = registers;
+        self.i = i;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.stack = stack;
+        self.rpl_flags = rpl_flags;
+        self.halted = halted;
+        self.selected_planes = selected_planes;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.pressed_keys = pressed_keys;
+        self.awaited_key = awaited_key;
+        self.waiting_for_vblank = waiting_for_vblank;
+        self.pixel_buffer = pixel_buffer;
+
+        Ok(())
     }
 
     pub fn key_press(&mut self, key: u8) {
-        self.current_pressed_key = Some(key);
+        self.pressed_keys |= 1 << (key & 0xf);
+    }
+
+    pub fn key_release(&mut self, key: u8) {
+        self.pressed_keys &= !(1 << (key & 0xf));
     }
 
-    pub fn key_release(&mut self, _key: u8) {
-        self.current_pressed_key = None;
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.pressed_keys & (1 << (key & 0xf)) != 0
     }
 
     pub fn get_pixel_buffer(&self) -> &PixelBuffer {
         &self.pixel_buffer
     }
 
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Overwrites memory from `address`, failing without changes when it runs past the end.
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) -> Result<(), String> {
+        let start = address as usize;
+        let target = self
+            .memory
+            .get_mut(start..start + bytes.len())
+            .ok_or(format!(
+                "Writing {} bytes at {:#06x} runs past the end of memory",
+                bytes.len(),
+                address
+            ))?;
+        target.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Return addresses of the active subroutine calls, innermost last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// Whether the program has exited through 00FD.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
     pub fn should_beep(&self) -> bool {
-        return self.sound_timer > 0;
+        self.sound_timer > 0
+    }
+
+    /// The 128 bit XO-CHIP audio pattern, played one bit at a time while the sound timer is active.
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_BYTES] {
+        &self.audio_pattern
+    }
+
+    /// Rate in bits per second at which the audio pattern is played, set through FX3A.
+    pub fn audio_playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+/// Registers from X to Y inclusive, in descending order when X is greater than Y.
+fn register_range(register_x: u8, register_y: u8) -> Box<dyn Iterator<Item = u8>> {
+    if register_x <= register_y {
+        Box::new(register_x..=register_y)
+    } else {
+        Box::new((register_y..=register_x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(rom: &[u8], quirks: Quirks, steps: usize) -> Machine {
+        let mut machine = Machine::from_rom(rom, quirks).unwrap();
+        for _ in 0..steps {
+            machine.step().unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn shift_source_test() {
+        // V1 = 0x81, V2 = 0x03, V1 <<= 1 (8X2E)
+        let rom = [0x61, 0x81, 0x62, 0x03, 0x81, 0x2e];
+
+        let machine = run(&rom, Quirks::chip48(), 3);
+        assert_eq!(machine.registers[0x1], 0x02);
+        assert_eq!(machine.registers[0xf], 1);
+
+        let machine = run(&rom, Quirks::cosmac_vip(), 3);
+        assert_eq!(machine.registers[0x1], 0x06);
+        assert_eq!(machine.registers[0xf], 0);
+    }
+
+    #[test]
+    fn load_store_increment_test() {
+        // I = 0x300, save V0..V2
+        let rom = [0xa3, 0x00, 0xf2, 0x55];
+
+        assert_eq!(run(&rom, Quirks::super_chip(), 2).i, 0x300);
+        assert_eq!(run(&rom, Quirks::chip48(), 2).i, 0x302);
+        assert_eq!(run(&rom, Quirks::cosmac_vip(), 2).i, 0x303);
+    }
+
+    #[test]
+    fn jump_with_offset_test() {
+        // V0 = 0x10, V3 = 0x20, jump to 0x300 + offset
+        let rom = [0x60, 0x10, 0x63, 0x20, 0xb3, 0x00];
+
+        assert_eq!(run(&rom, Quirks::cosmac_vip(), 3).program_counter, 0x310);
+        assert_eq!(run(&rom, Quirks::chip48(), 3).program_counter, 0x320);
+    }
+
+    #[test]
+    fn logic_resets_vf_test() {
+        // VF = 1, V0 |= V1
+        let rom = [0x6f, 0x01, 0x80, 0x11];
+
+        assert_eq!(run(&rom, Quirks::chip48(), 2).registers[0xf], 1);
+        assert_eq!(run(&rom, Quirks::cosmac_vip(), 2).registers[0xf], 0);
+    }
+
+    #[test]
+    fn clip_and_wrap_test() {
+        // V0 = 60, I = 0x206, draw a solid 8 pixel row at (60, 0)
+        let rom = [0x60, 0x3c, 0xa2, 0x06, 0xd0, 0x11, 0xff];
+
+        let mut quirks = Quirks::chip48();
+        let machine = run(&rom, quirks, 3);
+        assert!(machine.pixel_buffer.is_lit(63, 0));
+        assert!(!machine.pixel_buffer.is_lit(0, 0));
+
+        quirks.clip_sprites = false;
+        let machine = run(&rom, quirks, 3);
+        assert!(machine.pixel_buffer.is_lit(63, 0));
+        assert!(machine.pixel_buffer.is_lit(0, 0));
+    }
+
+    #[test]
+    fn display_wait_test() {
+        // draw, then V0 = 1
+        let rom = [0xd0, 0x01, 0x60, 0x01];
+
+        let machine = run(&rom, Quirks::cosmac_vip(), 2);
+        assert_eq!(machine.registers[0x0], 0);
+
+        let mut machine = Machine::from_rom(&rom, Quirks::cosmac_vip()).unwrap();
+        machine.run_frame(10).unwrap();
+        assert_eq!(machine.program_counter, 0x202);
+        machine.step().unwrap();
+        assert_eq!(machine.registers[0x0], 1);
+    }
+
+    #[test]
+    fn super_chip_draw_test() {
+        // hi-res, V0 = 120, I = big font "8", draw 16x16 at (120, 0)
+        let rom = [0x00, 0xff, 0x60, 0x78, 0x61, 0x08, 0xf1, 0x30, 0xd0, 0x20];
+
+        let machine = run(&rom, Quirks::super_chip(), 5);
+        assert!(machine.pixel_buffer.is_hires());
+        assert_eq!(machine.i, 0xa0 + 8 * 10);
+        assert!(machine.pixel_buffer.is_lit(122, 0));
+        assert!(machine.pixel_buffer.is_lit(127, 1));
+        assert!(!machine.pixel_buffer.is_lit(0, 1));
+    }
+
+    #[test]
+    fn scroll_test() {
+        // draw font "0" at (0, 0), scroll down 2, scroll right 4
+        let rom = [0xa0, 0x50, 0xd0, 0x05, 0x00, 0xc2, 0x00, 0xfb];
+
+        let machine = run(&rom, Quirks::super_chip(), 4);
+        assert!(!machine.pixel_buffer.is_lit(4, 0));
+        assert!(machine.pixel_buffer.is_lit(4, 2));
+        assert!(!machine.pixel_buffer.is_lit(0, 2));
+    }
+
+    #[test]
+    fn flags_and_exit_test() {
+        // V0 = 1, V1 = 2, save flags, V0 = 0, V1 = 0, load flags, exit, V0 = 9
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0xf1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xf1, 0x85, 0x00, 0xfd,
+            0x60, 0x09,
+        ];
+
+        let machine = run(&rom, Quirks::super_chip(), 8);
+        assert_eq!(machine.registers[..2], [1, 2]);
+        assert!(machine.is_halted());
+    }
+
+    #[test]
+    fn long_i_load_test() {
+        // V0 = 0, skip next if V0 == 0 over F000 NNNN, I := 0x1234
+        let rom = [0x30, 0x00, 0xf0, 0x00, 0x12, 0x34, 0xf0, 0x00, 0xab, 0xcd];
+
+        let machine = run(&rom, Quirks::default(), 1);
+        assert_eq!(machine.program_counter, 0x206);
+
+        let machine = run(&rom, Quirks::default(), 2);
+        assert_eq!(machine.i, 0xabcd);
+        assert_eq!(machine.program_counter, 0x20a);
+    }
+
+    #[test]
+    fn register_range_test() {
+        // V1 = 1, V2 = 2, V3 = 3, I = 0x300, save V1..V3, load V3..V1 in reverse
+        let rom = [
+            0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xa3, 0x00, 0x51, 0x32, 0x53, 0x13,
+        ];
+
+        let machine = run(&rom, Quirks::default(), 5);
+        assert_eq!(machine.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(machine.i, 0x300);
+
+        let machine = run(&rom, Quirks::default(), 6);
+        assert_eq!(machine.registers[1..4], [3, 2, 1]);
+    }
+
+    #[test]
+    fn planes_test() {
+        // select both planes, I = 0x20a, draw 1 row at (0, 0): plane 1 = 0x80, plane 2 = 0xc0
+        let rom = [
+            0xf3, 0x01, 0xa2, 0x0a, 0xd0, 0x01, 0x00, 0x00, 0x00, 0x00, 0x80, 0xc0,
+        ];
+
+        let machine = run(&rom, Quirks::default(), 3);
+        assert_eq!(machine.pixel_buffer.get(0, 0), 0b11);
+        assert_eq!(machine.pixel_buffer.get(1, 0), 0b10);
+        assert_eq!(machine.pixel_buffer.get(2, 0), 0);
+    }
+
+    #[test]
+    fn audio_test() {
+        // I = 0x20a, load pattern, V0 = 112, pitch = V0
+        let rom = [
+            0xa2, 0x0a, 0xf0, 0x02, 0x60, 0x70, 0xf0, 0x3a, 0x00, 0x00, 0xaa,
+        ];
+
+        let machine = run(&rom, Quirks::default(), 2);
+        assert_eq!(machine.audio_pattern()[0], 0xaa);
+        assert_eq!(machine.audio_playback_rate(), 4000.0);
+
+        let machine = run(&rom, Quirks::default(), 4);
+        assert_eq!(machine.audio_playback_rate(), 8000.0);
+    }
+
+    #[test]
+    fn error_test() {
+        let mut machine = Machine::from_rom(&[0x00, 0xee], Quirks::default()).unwrap();
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::StackUnderflow {
+                address: 0x200,
+                opcode: 0x00ee
+            })
+        );
+
+        let mut machine = Machine::from_rom(&[0x22, 0x00], Quirks::default()).unwrap();
+        for _ in 0..STACK_SIZE {
+            machine.step().unwrap();
+        }
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::StackOverflow {
+                address: 0x200,
+                opcode: 0x2200
+            })
+        );
+
+        let mut machine = Machine::from_rom(&[0xff, 0xff], Quirks::default()).unwrap();
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::UnknownOpcode {
+                address: 0x200,
+                opcode: 0xffff
+            })
+        );
+
+        // I = 0xfffe, save V0..V3
+        let rom = [0xf0, 0x00, 0xff, 0xfe, 0xf3, 0x55];
+        let mut machine = run(&rom, Quirks::default(), 1);
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::MemoryOutOfRange {
+                address: 0x204,
+                opcode: 0xf355,
+                target: 0xfffe,
+                accessed: 4
+            })
+        );
+
+        machine.program_counter = 0xffff;
+        assert_eq!(
+            machine.step(),
+            Err(MachineError::ProgramCounterOutOfRange { address: 0xffff })
+        );
+    }
+
+    #[test]
+    fn multiple_keys_test() {
+        // V0 = 5, skip next if key V0 is pressed, V1 = 1
+        let rom = [0x60, 0x05, 0xe0, 0x9e, 0x61, 0x01];
+
+        let mut machine = Machine::from_rom(&rom, Quirks::default()).unwrap();
+        machine.key_press(0x5);
+        machine.key_press(0x7);
+        machine.key_release(0x7);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.program_counter, 0x206);
+        assert!(machine.is_key_pressed(0x5));
+        assert!(!machine.is_key_pressed(0x7));
+    }
+
+    #[test]
+    fn wait_for_key_release_test() {
+        // V0 = key, V1 = 1
+        let rom = [0xf0, 0x0a, 0x61, 0x01];
+
+        let mut machine = Machine::from_rom(&rom, Quirks::default()).unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.program_counter, 0x200);
+
+        machine.key_press(0xa);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.program_counter, 0x200);
+
+        machine.key_release(0xa);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.registers[..2], [0xa, 1]);
+    }
+
+    #[test]
+    fn frame_timers_test() {
+        // V0 = 10, delay timer = V0, then loop forever
+        let rom = [0x60, 0x0a, 0xf0, 0x15, 0x12, 0x04];
+
+        let mut machine = Machine::from_rom(&rom, Quirks::default()).unwrap();
+        machine.run_frame(100).unwrap();
+        assert_eq!(machine.delay_timer, 9);
+
+        machine.run_frame(100).unwrap();
+        machine.run_frame(100).unwrap();
+        assert_eq!(machine.delay_timer, 7);
+    }
+
+    #[test]
+    fn seeded_random_test() {
+        // V0 = random & 0xff, V1 = random & 0x0f
+        let rom = [0xc0, 0xff, 0xc1, 0x0f];
+
+        let seeded = |seed| {
+            let mut machine = Machine::from_rom(&rom, Quirks::default())
+                .unwrap()
+                .with_random_source(Box::new(SeededRandom::new(seed)));
+            machine.step().unwrap();
+            machine.step().unwrap();
+            machine.registers
+        };
+
+        assert_eq!(seeded(42), seeded(42));
+        assert_ne!(seeded(42), seeded(43));
+        assert!(seeded(42)[1] <= 0x0f);
+    }
+
+    #[test]
+    fn save_state_test() {
+        // V0 = random, draw font "0", call 0x20a, which loops forever
+        let rom = [
+            0xc0, 0xff, 0xa0, 0x50, 0xd1, 0x15, 0x22, 0x0a, 0x00, 0x00, 0x12, 0x0a,
+        ];
+
+        let mut machine = Machine::from_rom(&rom, Quirks::default())
+            .unwrap()
+            .with_random_source(Box::new(SeededRandom::new(7)));
+        machine.run_frame(4).unwrap();
+        machine.key_press(0x3);
+        let state = machine.save_state();
+
+        let mut restored = Machine::from_rom(&[], Quirks::default())
+            .unwrap()
+            .with_random_source(Box::new(SeededRandom::new(0)));
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.pixel_buffer, machine.pixel_buffer);
+        assert_eq!(restored.stack, [0x208]);
+        assert!(restored.is_key_pressed(0x3));
+
+        // the random sequence continues where the snapshot left off
+        machine.program_counter = 0x200;
+        restored.program_counter = 0x200;
+        machine.step().unwrap();
+        restored.step().unwrap();
+        assert_eq!(restored.registers[0], machine.registers[0]);
+
+        let mut corrupted = state;
+        corrupted[20] ^= 0xff;
+        assert!(restored.load_state(&corrupted).is_err());
+    }
+
+    #[test]
+    fn invalid_save_state_test() {
+        let mut machine = Machine::from_rom(&[], Quirks::default())
+            .unwrap()
+            .with_random_source(Box::new(SeededRandom::new(0)));
+        let payload = machine.state_payload();
+
+        // the display and the 8 byte seeded random state close the payload
+        let random_len = 4 + 8;
+        let display = payload.len() - random_len - NUM_COLS * NUM_ROWS - 4;
+        // memory, PC, V0-VF, I, timers, empty stack, RPL flags and halted come first
+        let selected_planes = 4 + MEMORY_SIZE + 2 + 16 + 2 + 2 + 1 + 16 + 1;
+
+        let mut empty_display = payload[..display].to_vec();
+        empty_display.extend_from_slice(&[0, 0, 0, 0]);
+        empty_display.extend_from_slice(&payload[payload.len() - random_len..]);
+
+        let mut bright_pixel = payload.clone();
+        bright_pixel[display + 4] = 4;
+
+        let mut planes = payload.clone();
+        planes[selected_planes] = 0xff;
+
+        for invalid in [empty_display, bright_pixel, planes] {
+            assert!(machine.load_state(&state::encode(&invalid)).is_err());
+        }
+        assert_eq!(machine.state_payload(), payload);
     }
 }
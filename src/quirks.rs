@@ -0,0 +1,104 @@
+use std::str::FromStr;
+
+/// How FX55/FX65 leave the I register after saving or loading registers.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum IndexIncrement {
+    /// I is left untouched.
+    Unchanged,
+    /// I is incremented by X.
+    ByX,
+    /// I is incremented by X + 1, pointing just past the last byte accessed.
+    ByXPlusOne,
+}
+
+/// Interpretation of the opcodes whose behaviour differs between CHIP-8 platforms.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+
+    /// What FX55/FX65 do to I.
+    pub load_store_increment: IndexIncrement,
+
+    /// BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump_with_offset_uses_vx: bool,
+
+    /// DXYN clips sprites at the screen edges instead of wrapping them around.
+    pub clip_sprites: bool,
+
+    /// 8XY1/8XY2/8XY3 reset VF to 0.
+    pub logic_resets_vf: bool,
+
+    /// DXYN waits for the next vertical blank before execution continues.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// What this emulator did before quirks were configurable, and still does by default.
+    pub fn original() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: IndexIncrement::Unchanged,
+            jump_with_offset_uses_vx: false,
+            clip_sprites: true,
+            logic_resets_vf: false,
+            display_wait: false,
+        }
+    }
+
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: IndexIncrement::ByXPlusOne,
+            jump_with_offset_uses_vx: false,
+            clip_sprites: true,
+            logic_resets_vf: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: IndexIncrement::ByX,
+            jump_with_offset_uses_vx: true,
+            clip_sprites: true,
+            logic_resets_vf: false,
+            display_wait: false,
+        }
+    }
+
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: IndexIncrement::Unchanged,
+            jump_with_offset_uses_vx: true,
+            clip_sprites: true,
+            logic_resets_vf: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::original()
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Quirks, String> {
+        match name {
+            "original" => Ok(Quirks::original()),
+            "vip" | "cosmac-vip" => Ok(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Ok(Quirks::chip48()),
+            "schip" | "super-chip" => Ok(Quirks::super_chip()),
+            _ => Err(format!(
+                "Unknown quirks profile {}, expected one of original, vip, chip48 or schip",
+                name
+            )),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::convert::TryInto;
+
+/// Source of the random bytes used by CXNN.
+pub trait RandomSource: Send {
+    /// Next random byte.
+    fn next_u8(&mut self) -> u8;
+
+    /// Called once at the end of every 60 Hz frame.
+    fn frame(&mut self) {}
+
+    /// Internal state to store in save states. Stateless sources can keep the default.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores the internal state produced by `save_state`.
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn state_bytes<const N: usize>(state: &[u8]) -> Result<[u8; N], String> {
+    state.try_into().map_err(|_| {
+        format!(
+            "Expected {} bytes of random source state, got {}",
+            N,
+            state.len()
+        )
+    })
+}
+
+/// Deterministic xorshift64* generator, producing the same sequence for the same seed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        // xorshift gets stuck on a zero state, so spread the seed with a splitmix64 round
+        let mut state = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        state ^= state >> 31;
+
+        SeededRandom {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    /// Seeded from the operating system's entropy, for runs that don't need to be reproducible.
+    pub fn from_entropy() -> SeededRandom {
+        SeededRandom::new(fastrand::u64(..))
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.state = u64::from_le_bytes(state_bytes(state)?);
+        Ok(())
+    }
+}
+
+/// Size of the COSMAC VIP CHIP-8 interpreter, which sits at 0x000-0x1FF of the VIP's RAM.
+pub const VIP_INTERPRETER_LEN: usize = 0x200;
+
+const VIP_INTERPRETER_PAGE: usize = 0x100;
+
+/// Generator modelled on the COSMAC VIP interpreter's CXNN routine, which reads bytes from its
+/// own code page instead of running a proper generator.
+///
+/// A 16 bit seed has its low byte bumped every frame and on every call. The low byte indexes
+/// the interpreter's second page (VIP addresses 0x100-0x1FF), and the byte found there is added
+/// into the high byte, which becomes the random number.
+///
+/// That page is the VIP's interpreter, not the CHIP-8 program's RAM, so it has to be given as a
+/// dump of the original interpreter, which this crate does not ship.
+///
+/// This is an approximation of the routine as documented, it has not been checked against
+/// numbers drawn by a real VIP.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CosmacVipRandom {
+    seed: u16,
+    page: [u8; 0x100],
+}
+
+impl CosmacVipRandom {
+    /// A generator reading from `interpreter`, the 512 bytes of the VIP CHIP-8 interpreter.
+    pub fn new(interpreter: &[u8]) -> Result<CosmacVipRandom, String> {
+        if interpreter.len() != VIP_INTERPRETER_LEN {
+            return Err(format!(
+                "The VIP interpreter is {} bytes, got {}",
+                VIP_INTERPRETER_LEN,
+                interpreter.len()
+            ));
+        }
+
+        let mut page = [0; 0x100];
+        page.copy_from_slice(&interpreter[VIP_INTERPRETER_PAGE..]);
+        Ok(CosmacVipRandom { seed: 0, page })
+    }
+
+    pub fn with_seed(mut self, seed: u16) -> CosmacVipRandom {
+        self.seed = seed;
+        self
+    }
+}
+
+impl RandomSource for CosmacVipRandom {
+    fn next_u8(&mut self) -> u8 {
+        let [high, low] = self.seed.to_be_bytes();
+        let low = low.wrapping_add(1);
+        let high = high.wrapping_add(self.page[low as usize]);
+
+        self.seed = u16::from_be_bytes([high, low]);
+        high
+    }
+
+    fn frame(&mut self) {
+        let [high, low] = self.seed.to_be_bytes();
+        self.seed = u16::from_be_bytes([high, low.wrapping_add(1)]);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.seed.to_le_bytes().to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        self.seed = u16::from_le_bytes(state_bytes(state)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosmac_vip_random_test() {
+        let mut interpreter = vec![0u8; VIP_INTERPRETER_LEN];
+        interpreter[0x101] = 0x30;
+        interpreter[0x102] = 0x05;
+        interpreter[0x104] = 0x10;
+
+        let mut random = CosmacVipRandom::new(&interpreter).unwrap();
+        assert_eq!(random.next_u8(), 0x30);
+        assert_eq!(random.next_u8(), 0x35);
+
+        random.frame();
+        assert_eq!(random.next_u8(), 0x45);
+
+        let mut random = CosmacVipRandom::new(&interpreter)
+            .unwrap()
+            .with_seed(0x0100);
+        assert_eq!(random.next_u8(), 0x31);
+        assert!(CosmacVipRandom::new(&interpreter[..0x100]).is_err());
+    }
+}
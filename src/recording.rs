@@ -0,0 +1,360 @@
+//! Records presented frames as an animated GIF or a raw RGB stream.
+//!
+//! Both formats have a fixed size of `scale` times the hi-res resolution, so a low-res pixel
+//! is `2 * scale` image pixels wide and programs may switch resolution mid-recording. A raw
+//! stream is every frame's RGB bytes back to back with no header, for example for
+//! `ffmpeg -f rawvideo -pix_fmt rgb24 -s 128x64 -r 60 -i -`.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::display::{PixelBuffer, HIRES_NUM_COLS, HIRES_NUM_ROWS};
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum VideoFormat {
+    Gif,
+    /// Headerless 24-bit RGB frames, for piping into an external encoder.
+    Raw,
+}
+
+impl VideoFormat {
+    /// Format matching the extension of `path`, if it has a known one.
+    pub fn from_path(path: &str) -> Option<VideoFormat> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        extension.to_ascii_lowercase().parse().ok()
+    }
+
+    /// Format given on the command line, or else the one matching the extension of `path`.
+    /// Recording to stdout with `-` defaults to raw frames.
+    pub fn resolve(path: &str, format: Option<VideoFormat>) -> Result<VideoFormat, String> {
+        match format {
+            Some(format) => Ok(format),
+            None if path == "-" => Ok(VideoFormat::Raw),
+            None => VideoFormat::from_path(path).ok_or(format!(
+                "Unknown video format for {}, expected .gif or .rgb",
+                path
+            )),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Raw => "rgb",
+        }
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<VideoFormat, String> {
+        match name {
+            "gif" => Ok(VideoFormat::Gif),
+            "raw" | "rgb" => Ok(VideoFormat::Raw),
+            _ => Err(format!(
+                "Unknown video format {}, expected gif or raw",
+                name
+            )),
+        }
+    }
+}
+
+/// GIF delays are in hundredths of a second, so frame N starts at the centisecond
+/// `N * 100 / 60` and the delays alternate between 1 and 2 to average 60 Hz.
+fn centiseconds(frame: u64) -> u64 {
+    frame * 100 / FRAMES_PER_SECOND
+}
+
+/// A GIF frame waiting for the frame that replaces it, which decides its delay.
+struct PendingFrame {
+    pixels: Vec<u8>,
+    palette: Vec<[u8; 3]>,
+    /// Number of the frame it was first shown.
+    start: u64,
+}
+
+enum Sink {
+    Gif {
+        encoder: gif::Encoder<Box<dyn Write>>,
+        /// Palette of the file, frames shown with another one carry their own.
+        global_palette: Vec<[u8; 3]>,
+        /// Written once the picture changes, so identical frames are merged into one.
+        pending: Option<PendingFrame>,
+    },
+    Raw(Box<dyn Write>),
+}
+
+/// Writes every frame given to `record_frame` in a `VideoFormat`, until `finish` is called.
+pub struct VideoRecorder {
+    sink: Sink,
+    palette: Vec<[u8; 3]>,
+    scale: usize,
+    frames: u64,
+}
+
+impl VideoRecorder {
+    pub fn new(
+        writer: Box<dyn Write>,
+        format: VideoFormat,
+        palette: &[[u8; 3]],
+        scale: usize,
+    ) -> Result<VideoRecorder, String> {
+        if scale == 0 {
+            return Err("Video scale must be at least 1".to_string());
+        }
+
+        let sink = match format {
+            VideoFormat::Gif => {
+                let colors: Vec<u8> = palette.iter().flatten().copied().collect();
+                let mut encoder = gif::Encoder::new(
+                    writer,
+                    (HIRES_NUM_COLS * scale) as u16,
+                    (HIRES_NUM_ROWS * scale) as u16,
+                    &colors,
+                )
+                .map_err(|error| format!("GIF write failed: {}", error))?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(|error| format!("GIF write failed: {}", error))?;
+
+                Sink::Gif {
+                    encoder,
+                    global_palette: palette.to_vec(),
+                    pending: None,
+                }
+            }
+            VideoFormat::Raw => Sink::Raw(writer),
+        };
+
+        Ok(VideoRecorder {
+            sink,
+            palette: palette.to_vec(),
+            scale,
+            frames: 0,
+        })
+    }
+
+    /// Records to the file at `path`, or to stdout when `path` is `-`.
+    pub fn create(
+        path: &str,
+        format: VideoFormat,
+        palette: &[[u8; 3]],
+        scale: usize,
+    ) -> Result<VideoRecorder, String> {
+        let writer: Box<dyn Write> = if path == "-" {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            let file = File::create(path).map_err(|_| format!("Write failed to {}", path))?;
+            Box::new(BufWriter::new(file))
+        };
+
+        VideoRecorder::new(writer, format, palette, scale)
+    }
+
+    pub fn width(&self) -> usize {
+        HIRES_NUM_COLS * self.scale
+    }
+
+    pub fn height(&self) -> usize {
+        HIRES_NUM_ROWS * self.scale
+    }
+
+    /// Number of frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Colours used from the next frame on, such as after switching themes.
+    pub fn set_palette(&mut self, palette: &[[u8; 3]]) {
+        self.palette = palette.to_vec();
+    }
+
+    /// Size of a buffer pixel in the image.
+    fn pixel_size(&self, pixel_buffer: &PixelBuffer) -> usize {
+        self.width() / pixel_buffer.width()
+    }
+
+    /// Records one 60 Hz frame.
+    pub fn record_frame(&mut self, pixel_buffer: &PixelBuffer) -> Result<(), String> {
+        let frame = self.frames;
+        self.frames += 1;
+
+        let pixel_size = self.pixel_size(pixel_buffer);
+        let size = (self.width() as u16, self.height() as u16);
+        match &mut self.sink {
+            Sink::Gif {
+                encoder,
+                global_palette,
+                pending,
+            } => {
+                let next = PendingFrame {
+                    pixels: pixel_buffer.scaled_pixels(pixel_size),
+                    palette: self.palette.clone(),
+                    start: frame,
+                };
+                match pending {
+                    Some(shown) if shown.pixels == next.pixels && shown.palette == next.palette => {
+                        Ok(())
+                    }
+                    _ => match pending.replace(next) {
+                        Some(shown) => write_gif_frame(encoder, size, global_palette, shown, frame),
+                        None => Ok(()),
+                    },
+                }
+            }
+            Sink::Raw(writer) => writer
+                .write_all(&pixel_buffer.to_rgb(&self.palette, pixel_size))
+                .map_err(|error| format!("Video write failed: {}", error)),
+        }
+    }
+
+    /// Writes out the last frame and the end of the file.
+    pub fn finish(self) -> Result<(), String> {
+        let end = self.frames;
+        let size = (self.width() as u16, self.height() as u16);
+
+        match self.sink {
+            Sink::Gif {
+                mut encoder,
+                global_palette,
+                pending,
+            } => {
+                if let Some(shown) = pending {
+                    write_gif_frame(&mut encoder, size, &global_palette, shown, end)?;
+                }
+                encoder
+                    .into_inner()
+                    .and_then(|mut writer| writer.flush())
+                    .map_err(|error| format!("GIF write failed: {}", error))
+            }
+            Sink::Raw(mut writer) => writer
+                .flush()
+                .map_err(|error| format!("Video write failed: {}", error)),
+        }
+    }
+}
+
+/// Writes a frame shown until frame `end`.
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<Box<dyn Write>>,
+    (width, height): (u16, u16),
+    global_palette: &[[u8; 3]],
+    shown: PendingFrame,
+    end: u64,
+) -> Result<(), String> {
+    let palette = if shown.palette == global_palette {
+        None
+    } else {
+        Some(shown.palette.iter().flatten().copied().collect())
+    };
+    let mut frame = gif::Frame {
+        width,
+        height,
+        palette,
+        buffer: Cow::Owned(shown.pixels),
+        ..gif::Frame::default()
+    };
+
+    // A delay is at most u16::MAX centiseconds, longer frames are written several times
+    let mut remaining = centiseconds(end) - centiseconds(shown.start);
+    while remaining > 0 {
+        frame.delay = remaining.min(u16::MAX as u64) as u16;
+        remaining -= frame.delay as u64;
+        encoder
+            .write_frame(&frame)
+            .map_err(|error| format!("GIF write failed: {}", error))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::{DEFAULT_PALETTE, THEMES};
+    use std::sync::{Arc, Mutex};
+
+    /// Writer whose contents stay readable after the recorder takes ownership of it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Records three blank low-res frames followed by three hi-res frames with a lit pixel,
+    /// the last one with another palette.
+    fn record(format: VideoFormat) -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let mut recorder =
+            VideoRecorder::new(Box::new(buffer.clone()), format, &DEFAULT_PALETTE, 1).unwrap();
+
+        for _ in 0..3 {
+            recorder.record_frame(&PixelBuffer::lowres()).unwrap();
+        }
+        let mut hires = PixelBuffer::hires();
+        hires.toggle(1, 0, 0b01);
+        for _ in 0..2 {
+            recorder.record_frame(&hires).unwrap();
+        }
+        recorder.set_palette(&THEMES[1].palette);
+        recorder.record_frame(&hires).unwrap();
+        assert_eq!(recorder.frames(), 6);
+        recorder.finish().unwrap();
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        bytes
+    }
+
+    #[test]
+    fn gif_test() {
+        let gif = record(VideoFormat::Gif);
+        let mut decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (128, 64));
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((
+                frame.delay,
+                frame.buffer[..3].to_vec(),
+                frame.palette.is_some(),
+            ));
+        }
+        // 3 frames last 5 centiseconds, the next 2 last 3 and the last one 2
+        assert_eq!(
+            frames,
+            [
+                (5, vec![0, 0, 0], false),
+                (3, vec![0, 1, 0], false),
+                (2, vec![0, 1, 0], true)
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_test() {
+        let raw = record(VideoFormat::Raw);
+        let frame_len = 128 * 64 * 3;
+
+        assert_eq!(raw.len(), 6 * frame_len);
+        assert_eq!(
+            raw[3 * frame_len..3 * frame_len + 6],
+            [0, 0, 0, 255, 255, 255]
+        );
+        assert_eq!(
+            raw[5 * frame_len + 3..5 * frame_len + 6],
+            THEMES[1].palette[1]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::program::Machine;
+
+/// Frames between two full snapshots, the ones in between only keep what changed since.
+const KEYFRAME_INTERVAL: usize = 60;
+
+/// Changed bytes closer than this are stored as one run, which costs less than a new one.
+const MIN_UNCHANGED_RUN: usize = 16;
+
+/// One frame, as the bytes of a state payload that differ from its keyframe.
+struct Snapshot {
+    /// Payload shared by this snapshot and the ones after it up to the next keyframe.
+    keyframe: Rc<Vec<u8>>,
+    /// Offsets in the keyframe and the bytes found there in this frame, empty for the
+    /// keyframe itself.
+    changes: Vec<(usize, Vec<u8>)>,
+}
+
+impl Snapshot {
+    fn keyframe(payload: Vec<u8>) -> Snapshot {
+        Snapshot {
+            keyframe: Rc::new(payload),
+            changes: Vec::new(),
+        }
+    }
+
+    fn delta(keyframe: &Rc<Vec<u8>>, payload: &[u8]) -> Snapshot {
+        let mut changes: Vec<(usize, Vec<u8>)> = Vec::new();
+
+        for (offset, (old, new)) in keyframe.iter().zip(payload).enumerate() {
+            if old == new {
+                continue;
+            }
+            match changes.last_mut() {
+                Some((start, bytes)) if offset - (*start + bytes.len()) < MIN_UNCHANGED_RUN => {
+                    bytes.extend_from_slice(&payload[*start + bytes.len()..=offset]);
+                }
+                _ => changes.push((offset, vec![*new])),
+            }
+        }
+
+        Snapshot {
+            keyframe: Rc::clone(keyframe),
+            changes,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let mut payload = self.keyframe.to_vec();
+        for (offset, bytes) in &self.changes {
+            payload[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        payload
+    }
+
+    /// Bytes held by the changes, leaving out the keyframe they share.
+    fn changes_len(&self) -> usize {
+        self.changes.iter().map(|(_, bytes)| bytes.len()).sum()
+    }
+}
+
+/// Ring buffer of per-frame snapshots, dropping the oldest once full.
+///
+/// Snapshots are state payloads without the header and checksum of a save state. Only one
+/// frame in `KEYFRAME_INTERVAL` is stored in full, the others keep the bytes that differ from
+/// it, which are few since most of the 64 KiB of memory never changes.
+pub struct RewindBuffer {
+    states: VecDeque<Snapshot>,
+    capacity: usize,
+    /// Snapshots captured since the last keyframe, including it.
+    since_keyframe: usize,
+}
+
+impl RewindBuffer {
+    /// A buffer holding up to `frames` snapshots.
+    pub fn new(frames: usize) -> RewindBuffer {
+        RewindBuffer {
+            states: VecDeque::with_capacity(frames),
+            capacity: frames,
+            since_keyframe: 0,
+        }
+    }
+
+    /// A buffer holding `seconds` worth of 60 Hz frames.
+    pub fn with_seconds(seconds: u32) -> RewindBuffer {
+        RewindBuffer::new(seconds as usize * 60)
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+        self.since_keyframe = 0;
+    }
+
+    /// Approximate number of bytes held by the snapshots.
+    pub fn memory_usage(&self) -> usize {
+        let mut usage = 0;
+        let mut keyframe: Option<&Rc<Vec<u8>>> = None;
+        for state in &self.states {
+            if !keyframe.is_some_and(|keyframe| Rc::ptr_eq(keyframe, &state.keyframe)) {
+                usage += state.keyframe.len();
+                keyframe = Some(&state.keyframe);
+            }
+            usage += state.changes_len();
+        }
+        usage
+    }
+
+    /// Snapshots the machine, to be called once per frame.
+    pub fn capture(&mut self, machine: &Machine) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+
+        let payload = machine.state_payload();
+        let snapshot = match self.states.back() {
+            // the payload only changes size with the stack depth, resolution or random source
+            Some(last)
+                if self.since_keyframe < KEYFRAME_INTERVAL
+                    && last.keyframe.len() == payload.len() =>
+            {
+                self.since_keyframe += 1;
+                Snapshot::delta(&last.keyframe, &payload)
+            }
+            _ => {
+                self.since_keyframe = 1;
+                Snapshot::keyframe(payload)
+            }
+        };
+        self.states.push_back(snapshot);
+    }
+
+    /// Restores the most recent snapshot and drops it, returning false once the buffer is empty.
+    pub fn rewind(&mut self, machine: &mut Machine) -> Result<bool, String> {
+        match self.states.pop_back() {
+            Some(state) => machine.load_state_payload(&state.payload()).map(|_| true),
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use crate::random::SeededRandom;
+
+    fn seeded_machine(rom: &[u8]) -> Machine {
+        Machine::from_rom(rom, Quirks::default())
+            .unwrap()
+            .with_random_source(Box::new(SeededRandom::new(0)))
+    }
+
+    #[test]
+    fn rewind_test() {
+        // V0 += 1, jump back
+        let rom = [0x70, 0x01, 0x12, 0x00];
+        let mut machine = seeded_machine(&rom);
+        let mut buffer = RewindBuffer::new(3);
+
+        for _ in 0..5 {
+            buffer.capture(&machine);
+            machine.run_frame(2).unwrap();
+        }
+        assert_eq!(buffer.len(), 3);
+
+        let mut frames = 0;
+        while buffer.rewind(&mut machine).unwrap() {
+            frames += 1;
+        }
+        assert_eq!(frames, 3);
+
+        // back at the oldest kept frame, two frames after the start
+        let mut expected = seeded_machine(&rom);
+        expected.run_frame(2).unwrap();
+        expected.run_frame(2).unwrap();
+        assert_eq!(machine.save_state(), expected.save_state());
+    }
+
+    #[test]
+    fn delta_test() {
+        // V0 += 1, draw the sprite at I, jump back
+        let rom = [0x70, 0x01, 0xd0, 0x05, 0x12, 0x00];
+        let mut machine = seeded_machine(&rom);
+        let mut buffer = RewindBuffer::new(KEYFRAME_INTERVAL * 2);
+
+        let mut states = Vec::new();
+        for _ in 0..KEYFRAME_INTERVAL + 10 {
+            buffer.capture(&machine);
+            states.push(machine.save_state());
+            machine.run_frame(3).unwrap();
+        }
+
+        // two keyframes and small deltas, instead of 70 copies of the memory
+        let payload_len = machine.state_payload().len();
+        assert!(buffer.memory_usage() < 3 * payload_len);
+
+        while let Some(expected) = states.pop() {
+            assert!(buffer.rewind(&mut machine).unwrap());
+            assert_eq!(machine.save_state(), expected);
+        }
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+//! Exports a `PixelBuffer` as a PNG or binary PPM image.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::display::PixelBuffer;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ImageFormat {
+    Png,
+    /// Binary PPM (P6), readable by most image tools without any library.
+    Ppm,
+}
+
+impl ImageFormat {
+    /// Format matching the extension of `path`, if it has a known one.
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        extension.to_ascii_lowercase().parse().ok()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ImageFormat, String> {
+        match name {
+            "png" => Ok(ImageFormat::Png),
+            "ppm" => Ok(ImageFormat::Ppm),
+            _ => Err(format!(
+                "Unknown image format {}, expected png or ppm",
+                name
+            )),
+        }
+    }
+}
+
+impl PixelBuffer {
+    /// RGB bytes of the buffer, row by row, with every pixel drawn as a `scale` by `scale`
+    /// square in the colour its index selects in `colors`, such as a `Palette`.
+    pub fn to_rgb(&self, colors: &[[u8; 3]], scale: usize) -> Vec<u8> {
+        self.scaled_pixels(scale)
+            .iter()
+            .flat_map(|pixel| colors[*pixel as usize])
+            .collect()
+    }
+
+    /// Encodes the buffer as an image `scale` times its native resolution.
+    pub fn write_image<W: Write>(
+        &self,
+        writer: W,
+        format: ImageFormat,
+        colors: &[[u8; 3]],
+        scale: usize,
+    ) -> io::Result<()> {
+        let (width, height) = (self.width() * scale, self.height() * scale);
+        let rgb = self.to_rgb(colors, scale);
+
+        match format {
+            ImageFormat::Png => {
+                let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(&rgb)?;
+                writer.finish()?;
+                Ok(())
+            }
+            ImageFormat::Ppm => {
+                let mut writer = writer;
+                write!(writer, "P6\n{} {}\n255\n", width, height)?;
+                writer.write_all(&rgb)?;
+                writer.flush()
+            }
+        }
+    }
+
+    /// Writes the buffer to `path` as an image `scale` times its native resolution.
+    pub fn save_image(
+        &self,
+        path: &str,
+        format: ImageFormat,
+        colors: &[[u8; 3]],
+        scale: usize,
+    ) -> Result<(), String> {
+        let file = File::create(path).map_err(|_| format!("Write failed to {}", path))?;
+
+        self.write_image(BufWriter::new(file), format, colors, scale)
+            .map_err(|error| format!("Write failed to {}: {}", path, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::DEFAULT_PALETTE;
+
+    fn buffer() -> PixelBuffer {
+        let mut buffer = PixelBuffer::new(2, 1);
+        buffer.toggle(1, 0, 0b11);
+        buffer
+    }
+
+    #[test]
+    fn ppm_test() {
+        let mut image = Vec::new();
+        buffer()
+            .write_image(&mut image, ImageFormat::Ppm, &DEFAULT_PALETTE, 2)
+            .unwrap();
+
+        let mut expected = b"P6\n4 2\n255\n".to_vec();
+        for _ in 0..2 {
+            expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 102, 34, 0, 102, 34, 0]);
+        }
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn png_test() {
+        let mut image = Vec::new();
+        buffer()
+            .write_image(&mut image, ImageFormat::Png, &DEFAULT_PALETTE, 1)
+            .unwrap();
+
+        let mut reader = png::Decoder::new(image.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+
+        assert_eq!((info.width, info.height), (2, 1));
+        assert_eq!(pixels, [0, 0, 0, 102, 34, 0]);
+    }
+
+    #[test]
+    fn format_from_path_test() {
+        assert_eq!(ImageFormat::from_path("shot.PNG"), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path("shot.ppm"), Some(ImageFormat::Ppm));
+        assert_eq!(ImageFormat::from_path("shot"), None);
+    }
+}
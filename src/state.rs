@@ -0,0 +1,215 @@
+//! Binary container used by `Machine::save_state` and `Machine::load_state`.
+//!
+//! Every multi-byte integer is little endian. A state is laid out as:
+//!
+//! | Bytes | Contents                                         |
+//! |-------|--------------------------------------------------|
+//! | 4     | Magic `C8ST`                                     |
+//! | 2     | Format version, currently 1                      |
+//! | 4     | Payload length N                                 |
+//! | N     | Payload                                          |
+//! | 4     | CRC-32 (IEEE) of the payload                     |
+//!
+//! Version 1 payloads hold, in order:
+//!
+//! - memory: u32 length followed by the bytes
+//! - program counter: u16
+//! - V0-VF: 16 bytes
+//! - I: u16
+//! - delay timer, sound timer: u8 each
+//! - stack: u8 depth followed by u16 return addresses, oldest first
+//! - RPL flags: 16 bytes
+//! - halted: u8 boolean
+//! - selected planes: u8
+//! - audio pattern: 16 bytes
+//! - pitch: u8
+//! - pressed keys: u16 bitmask
+//! - key awaited by FX0A: u8, 0xFF when none
+//! - waiting for vertical blank: u8 boolean
+//! - pixel buffer: u16 width, u16 height, then one colour index byte per pixel, row by row
+//! - random source state: u32 length followed by the bytes
+
+const MAGIC: &[u8; 4] = b"C8ST";
+pub const VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 4 + 2 + 4;
+const CHECKSUM_LEN: usize = 4;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(0xffff_ffffu32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+/// Wraps a payload with the header and checksum.
+pub(crate) fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut state = Vec::with_capacity(HEADER_LEN + payload.len() + CHECKSUM_LEN);
+    state.extend_from_slice(MAGIC);
+    state.extend_from_slice(&VERSION.to_le_bytes());
+    state.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    state.extend_from_slice(payload);
+    state.extend_from_slice(&crc32(payload).to_le_bytes());
+    state
+}
+
+/// Validates the header and checksum, returning the payload.
+pub(crate) fn decode(state: &[u8]) -> Result<&[u8], String> {
+    let mut reader = StateReader::new(state);
+
+    if reader.bytes(MAGIC.len())? != MAGIC {
+        return Err("Not a save state, the magic header is missing".to_string());
+    }
+
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(format!(
+            "Unsupported save state version {}, expected {}",
+            version, VERSION
+        ));
+    }
+
+    let payload_len = reader.u32()? as usize;
+    let payload = reader.bytes(payload_len)?;
+    let checksum = reader.u32()?;
+
+    if checksum != crc32(payload) {
+        return Err("Save state is corrupted, the checksum does not match".to_string());
+    }
+
+    Ok(payload)
+}
+
+#[derive(Default)]
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Writes a u32 length followed by the bytes.
+    pub fn sized_bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader { bytes, position: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or("Save state is truncated")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    /// Reads a u32 length followed by that many bytes.
+    pub fn sized_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    /// Fails unless every byte has been read.
+    pub fn finish(self) -> Result<(), String> {
+        if self.position != self.bytes.len() {
+            return Err("Save state has unexpected trailing data".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_test() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn container_test() {
+        let state = encode(&[1, 2, 3]);
+        assert_eq!(decode(&state), Ok(&[1u8, 2, 3][..]));
+
+        let mut corrupted = state.clone();
+        corrupted[HEADER_LEN] = 9;
+        assert!(decode(&corrupted).is_err());
+
+        let mut wrong_version = state;
+        wrong_version[4] = 2;
+        assert!(decode(&wrong_version).is_err());
+
+        assert!(decode(b"C8").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! Opt-in execution trace, one record per executed instruction.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::instruction::{instruction_name, Instruction};
+use crate::program::Machine;
+use crate::quirks::Quirks;
+
+/// Registers, I and timers at one point in time.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct RegisterSnapshot {
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl RegisterSnapshot {
+    pub fn of(machine: &Machine) -> RegisterSnapshot {
+        RegisterSnapshot {
+            registers: *machine.registers(),
+            i: machine.i(),
+            delay_timer: machine.delay_timer(),
+            sound_timer: machine.sound_timer(),
+        }
+    }
+}
+
+/// An executed instruction and the registers around it.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct TraceRecord {
+    pub address: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    /// Quirks the instruction ran with, which decide how some of them are written.
+    pub quirks: Quirks,
+    pub before: RegisterSnapshot,
+    pub after: RegisterSnapshot,
+}
+
+/// Receives a record for every instruction executed by `Machine::step`.
+pub trait Tracer: Send {
+    fn record(&mut self, record: &TraceRecord);
+
+    /// Writes out buffered records, reporting any error met while recording.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TraceFormat {
+    /// One aligned line per record.
+    Text,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<TraceFormat, String> {
+        match name {
+            "text" => Ok(TraceFormat::Text),
+            "jsonl" | "json" => Ok(TraceFormat::JsonLines),
+            _ => Err(format!(
+                "Unknown trace format {}, expected text or jsonl",
+                name
+            )),
+        }
+    }
+}
+
+/// Which records are kept. An empty filter keeps everything.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<u16>>,
+    /// `Instruction` variant names, such as `Draw`, compared case insensitively.
+    pub instructions: Vec<String>,
+}
+
+impl TraceFilter {
+    /// Parses an address range written as two hex addresses, such as `200-2ff`.
+    pub fn parse_address_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+        let invalid = || format!("Invalid address range {}, expected start-end in hex", range);
+
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let parse = |address: &str| {
+            u16::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|_| invalid())
+        };
+        Ok(parse(start)?..=parse(end)?)
+    }
+
+    pub fn matches(&self, record: &TraceRecord) -> bool {
+        let in_range = self
+            .addresses
+            .as_ref()
+            .map(|range| range.contains(&record.address))
+            .unwrap_or(true);
+        let of_kind = self.instructions.is_empty() || {
+            let name = instruction_name(&record.instruction);
+            self.instructions
+                .iter()
+                .any(|kind| kind.eq_ignore_ascii_case(&name))
+        };
+
+        in_range && of_kind
+    }
+}
+
+fn format_text(record: &TraceRecord) -> String {
+    let snapshot = |snapshot: &RegisterSnapshot| {
+        let registers: String = snapshot
+            .registers
+            .iter()
+            .map(|value| format!("{:02X}", value))
+            .collect();
+        format!(
+            "V={} I={:04X} DT={:02X} ST={:02X}",
+            registers, snapshot.i, snapshot.delay_timer, snapshot.sound_timer
+        )
+    };
+
+    format!(
+        "{:04X} {:04X} {:<20} {} -> {}\n",
+        record.address,
+        record.opcode,
+        record.instruction.to_string_with(&record.quirks),
+        snapshot(&record.before),
+        snapshot(&record.after)
+    )
+}
+
+fn format_json(record: &TraceRecord) -> String {
+    let snapshot = |snapshot: &RegisterSnapshot| {
+        let registers: Vec<String> = snapshot
+            .registers
+            .iter()
+            .map(|value| value.to_string())
+            .collect();
+        format!(
+            r#"{{"v":[{}],"i":{},"dt":{},"st":{}}}"#,
+            registers.join(","),
+            snapshot.i,
+            snapshot.delay_timer,
+            snapshot.sound_timer
+        )
+    };
+
+    // Mnemonics never contain quotes or backslashes, so nothing needs escaping
+    let mut line = String::new();
+    writeln!(
+        line,
+        r#"{{"pc":{},"opcode":{},"kind":"{}","instruction":"{}","before":{},"after":{}}}"#,
+        record.address,
+        record.opcode,
+        instruction_name(&record.instruction),
+        record.instruction.to_string_with(&record.quirks),
+        snapshot(&record.before),
+        snapshot(&record.after)
+    )
+    .unwrap();
+    line
+}
+
+/// Writes filtered records to any writer, such as a buffered file.
+pub struct TraceWriter<W: Write + Send> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    /// The first write error, reported by `flush` since `record` cannot fail.
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Send> TraceWriter<W> {
+    pub fn new(writer: W, format: TraceFormat, filter: TraceFilter) -> TraceWriter<W> {
+        TraceWriter {
+            writer,
+            format,
+            filter,
+            error: None,
+        }
+    }
+}
+
+impl<W: Write + Send> Tracer for TraceWriter<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() || !self.filter.matches(record) {
+            return;
+        }
+
+        let line = match self.format {
+            TraceFormat::Text => format_text(record),
+            TraceFormat::JsonLines => format_json(record),
+        };
+        if let Err(error) = self.writer.write_all(line.as_bytes()) {
+            self.error = Some(error);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Quirks;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer whose contents stay readable after the machine takes ownership of the tracer.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(format: TraceFormat, filter: TraceFilter) -> String {
+        // V0 = 1, V1 = 2, I = 0x300
+        let rom = [0x60, 0x01, 0x61, 0x02, 0xa3, 0x00];
+        let buffer = SharedBuffer::default();
+        let mut machine = Machine::from_rom(&rom, Quirks::default())
+            .unwrap()
+            .with_tracer(Box::new(TraceWriter::new(buffer.clone(), format, filter)));
+
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        machine.flush_trace().unwrap();
+
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn text_trace_test() {
+        let filter = TraceFilter {
+            addresses: Some(TraceFilter::parse_address_range("202-203").unwrap()),
+            instructions: Vec::new(),
+        };
+
+        assert_eq!(
+            trace(TraceFormat::Text, filter),
+            "0202 6102 LD V1, 0x02          \
+             V=01000000000000000000000000000000 I=0000 DT=00 ST=00 -> \
+             V=01020000000000000000000000000000 I=0000 DT=00 ST=00\n"
+        );
+    }
+
+    #[test]
+    fn json_trace_test() {
+        let filter = TraceFilter {
+            addresses: None,
+            instructions: vec!["storeaddrtoi".to_string()],
+        };
+        let zeros = "0,0,0,0,0,0,0,0,0,0,0,0,0,0";
+
+        assert_eq!(
+            trace(TraceFormat::JsonLines, filter),
+            format!(
+                r#"{{"pc":516,"opcode":41728,"kind":"StoreAddrToI","instruction":"LD I, 0x300","before":{{"v":[1,2,{z}],"i":0,"dt":0,"st":0}},"after":{{"v":[1,2,{z}],"i":768,"dt":0,"st":0}}}}"#,
+                z = zeros
+            ) + "\n"
+        );
+    }
+
+    #[test]
+    fn address_range_test() {
+        assert_eq!(
+            TraceFilter::parse_address_range("0x200-2FF"),
+            Ok(0x200..=0x2ff)
+        );
+        assert!(TraceFilter::parse_address_range("200").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! Where the picture goes in a window of any size.
+
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Scaling {
+    /// Every pixel is the same whole number of output pixels, keeping edges crisp.
+    Integer,
+    /// The picture fills as much of the window as its aspect ratio allows.
+    Fit,
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Scaling, String> {
+        match name {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            _ => Err(format!("Unknown scaling {}, expected integer or fit", name)),
+        }
+    }
+}
+
+/// Width to height ratio of a pixel on the original display.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct PixelAspect(pub f64);
+
+impl PixelAspect {
+    pub const SQUARE: PixelAspect = PixelAspect(1.0);
+}
+
+impl FromStr for PixelAspect {
+    type Err = String;
+
+    /// Parses a ratio such as `4:3`, or a single number.
+    fn from_str(ratio: &str) -> Result<PixelAspect, String> {
+        let invalid = || format!("Invalid pixel aspect {}, expected width:height", ratio);
+        let number = |value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value > 0.0)
+                .ok_or_else(invalid)
+        };
+
+        match ratio.split_once(':') {
+            Some((width, height)) => Ok(PixelAspect(number(width)? / number(height)?)),
+            None => Ok(PixelAspect(number(ratio)?)),
+        }
+    }
+}
+
+/// Rectangle of the output covered by the picture, centred with borders around it.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    /// Places a `columns` by `rows` picture in an `output_width` by `output_height` output,
+    /// which is in physical pixels on HiDPI displays.
+    pub fn new(
+        (output_width, output_height): (u32, u32),
+        (columns, rows): (usize, usize),
+        scaling: Scaling,
+        aspect: PixelAspect,
+    ) -> Viewport {
+        let (columns, rows) = (columns as f64, rows as f64);
+        let (output_width, output_height) = (output_width as f64, output_height as f64);
+
+        let (width, height) = match scaling {
+            Scaling::Fit => {
+                let pixel_width = (output_width / columns).min(output_height / rows * aspect.0);
+                (pixel_width * columns, pixel_width / aspect.0 * rows)
+            }
+            Scaling::Integer => {
+                // the largest whole pixel width whose rounded height still fits, at least 1
+                let pixel_size = |pixel_width: f64| (pixel_width, (pixel_width / aspect.0).round());
+                let mut pixel_width = (output_width / columns).floor().max(1.0);
+                while pixel_width > 1.0 && pixel_size(pixel_width).1 * rows > output_height {
+                    pixel_width -= 1.0;
+                }
+                let (pixel_width, pixel_height) = pixel_size(pixel_width);
+                (pixel_width * columns, pixel_height.max(1.0) * rows)
+            }
+        };
+
+        let (width, height) = (width.floor(), height.floor());
+        Viewport {
+            x: ((output_width - width) / 2.0).max(0.0) as u32,
+            y: ((output_height - height) / 2.0).max(0.0) as u32,
+            width: width as u32,
+            height: height as u32,
+        }
+    }
+
+    /// Output column where picture column `column` of `columns` starts, so that neighbouring
+    /// pixels meet without gaps when pixels are not a whole number of output pixels wide.
+    pub fn column_start(&self, column: usize, columns: usize) -> u32 {
+        self.x + (column as u64 * self.width as u64 / columns as u64) as u32
+    }
+
+    /// Output row where picture row `row` of `rows` starts.
+    pub fn row_start(&self, row: usize, rows: usize) -> u32 {
+        self.y + (row as u64 * self.height as u64 / rows as u64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_scaling_test() {
+        let viewport = Viewport::new((700, 400), (64, 32), Scaling::Integer, PixelAspect::SQUARE);
+        assert_eq!(
+            viewport,
+            Viewport {
+                x: 30,
+                y: 40,
+                width: 640,
+                height: 320
+            }
+        );
+
+        // pixels twice as tall as they are wide
+        let tall: PixelAspect = "1:2".parse().unwrap();
+        let viewport = Viewport::new((700, 400), (64, 32), Scaling::Integer, tall);
+        assert_eq!((viewport.width, viewport.height), (384, 384));
+    }
+
+    #[test]
+    fn fit_scaling_test() {
+        let viewport = Viewport::new((1000, 400), (128, 64), Scaling::Fit, PixelAspect::SQUARE);
+        assert_eq!(
+            viewport,
+            Viewport {
+                x: 100,
+                y: 0,
+                width: 800,
+                height: 400
+            }
+        );
+        assert_eq!(viewport.column_start(64, 128), 500);
+        assert_eq!(viewport.row_start(64, 64), 400);
+    }
+
+    #[test]
+    fn pixel_aspect_test() {
+        assert_eq!("4:3".parse(), Ok(PixelAspect(4.0 / 3.0)));
+        assert_eq!("0.5".parse(), Ok(PixelAspect(0.5)));
+        assert!("0:1".parse::<PixelAspect>().is_err());
+        assert!("wide".parse::<PixelAspect>().is_err());
+    }
+}